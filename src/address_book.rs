@@ -0,0 +1,80 @@
+use std::{collections::HashMap, env, fs, path::PathBuf};
+
+// Labels for wallets and contracts, stored as a JSON object mapping addresses to labels
+pub struct AddressBook {
+    path: Option<PathBuf>,
+    labels: HashMap<String, String>,
+}
+
+impl AddressBook {
+    pub fn new() -> Self {
+        AddressBook {
+            path: None,
+            labels: HashMap::new(),
+        }
+    }
+
+    // Reads HYPERTUI_LABELS, or labels.json in the hypertui config directory
+    pub fn load() -> Result<Self, String> {
+        let path = match env::var("HYPERTUI_LABELS") {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => dirs::config_dir().map(|dir| dir.join("hypertui").join("labels.json")),
+        };
+        let labels = match &path {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(path)
+                    .map_err(|err| format!("could not read labels {}: {}", path.display(), err))?;
+                serde_json::from_str::<HashMap<String, String>>(&content)
+                    .map_err(|err| format!("labels {}: {}", path.display(), err))?
+                    .into_iter()
+                    .map(|(address, label)| (address.to_lowercase(), label))
+                    .collect()
+            }
+            _ => HashMap::new(),
+        };
+        Ok(AddressBook { path, labels })
+    }
+
+    pub fn label(&self, address: &str) -> Option<&str> {
+        self.labels
+            .get(&address.to_lowercase())
+            .map(|label| label.as_str())
+    }
+
+    // An empty label removes the address from the book
+    pub fn set_label(&mut self, address: &str, label: &str) -> Result<(), String> {
+        let label = label.trim();
+        if label.is_empty() {
+            self.labels.remove(&address.to_lowercase());
+        } else {
+            self.labels
+                .insert(address.to_lowercase(), label.to_string());
+        }
+        self.save()
+    }
+
+    // Case insensitive match against the address and its label
+    pub fn matches(&self, address: &str, query: &str) -> bool {
+        let query = query.to_lowercase();
+        address.to_lowercase().contains(&query)
+            || self
+                .label(address)
+                .is_some_and(|label| label.to_lowercase().contains(&query))
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = self.path.as_ref().ok_or("no config directory")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        let content = serde_json::to_string_pretty(&self.labels).map_err(|err| err.to_string())?;
+        fs::write(path, content)
+            .map_err(|err| format!("could not write labels {}: {}", path.display(), err))
+    }
+}
+
+impl Default for AddressBook {
+    fn default() -> Self {
+        AddressBook::new()
+    }
+}
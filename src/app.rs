@@ -1,31 +1,94 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, Utc};
+use hypertui::{
+    address_book::AddressBook,
+    cache::{self, TransferKinds},
+    config::Config,
+    ens::{self, EnsNames},
+    export::{self, ExportFormat},
+    history::{History, HistoryEntry, QueryOutcome, RowCounts},
+    hypersync::{QueryError, TransferBatch},
+    query::WalletQuery,
+    tokens::{self, TokenMetadata, TokenRegistry},
+    transfers::{Erc20Transfer, Transfers},
+};
 use ratatui::widgets::{ListState, ScrollbarState, TableState};
-use serde::Serialize;
+use tokio::{
+    sync::mpsc::{self, error::TryRecvError, UnboundedReceiver, UnboundedSender},
+    task::JoinHandle,
+};
 
 const LINE_HEIGHT: usize = 1;
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+
+// What the task running a query forwards to the UI
+pub enum QueryEvent {
+    Batch(TransferBatch),
+    Finished,
+    Failed(QueryError),
+}
 
-pub enum Chain {
-    Mainnet(String),
-    Optimism(String),
-    Arbitrum(String),
+// A short message shown over the current screen, e.g. the result of an export
+pub struct Toast {
+    pub message: String,
+    pub is_error: bool,
+    pub shown_at: Instant,
 }
-pub struct WalletQuery {
-    pub address: String,
-    pub chain: Chain,
-    pub regular_transfers: bool,
-    pub erc20_transfers: bool,
-    pub erc721_transfers: bool,
-    pub start_block: String,
+
+pub struct QueryProgress {
+    pub started_at: Instant,
+    pub from_block: u64,
+    pub to_block: Option<u64>,
+    pub is_resolved: bool,
+    pub next_block: u64,
+    pub archive_height: Option<u64>,
 }
 
-impl WalletQuery {
-    fn new() -> Self {
-        WalletQuery {
-            address: String::new(),
-            chain: Chain::Mainnet("https://eth.hypersync.xyz".to_string()),
-            regular_transfers: true,
-            erc20_transfers: true,
-            erc721_transfers: false,
-            start_block: String::from("1"),
+impl QueryProgress {
+    pub fn new(from_block: u64) -> Self {
+        QueryProgress {
+            started_at: Instant::now(),
+            from_block,
+            to_block: None,
+            is_resolved: false,
+            next_block: from_block,
+            archive_height: None,
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    pub fn blocks_scanned(&self) -> u64 {
+        self.next_block.saturating_sub(self.from_block)
+    }
+
+    pub fn ratio(&self) -> f64 {
+        if !self.is_resolved {
+            return 0.0;
+        }
+        // The stream stops at the requested end block, or at the chain head for "latest"
+        match self.to_block.or(self.archive_height) {
+            Some(height) if height > self.from_block => {
+                (self.blocks_scanned() as f64 / (height - self.from_block) as f64).min(1.0)
+            }
+            Some(_) => 1.0,
+            None => 0.0,
+        }
+    }
+
+    pub fn blocks_per_second(&self) -> f64 {
+        let seconds = self.elapsed().as_secs_f64();
+        if self.is_resolved && seconds > 0.0 {
+            self.blocks_scanned() as f64 / seconds
+        } else {
+            0.0
         }
     }
 }
@@ -53,31 +116,13 @@ impl<'b> QueryListState<'b> {
     }
 }
 
-#[derive(Serialize)]
-pub struct Erc20Transfer {
-    pub hash: String,
-    pub block: String,
-    pub contract: String,
-    pub to: String,
-    pub from: String,
-    pub amount: String,
-}
-
-#[derive(Serialize)]
-pub struct Erc721Transfer {
-    pub hash: String,
-    pub block: String,
-    pub contract: String,
-    pub to: String,
-    pub from: String,
-    pub token_id: String,
-}
-
 pub enum CurrentScreen {
     Startup,
     QueryBuilder,
     Main,
     Loading,
+    Error,
+    History,
 }
 
 pub struct TabsState<'a> {
@@ -106,6 +151,8 @@ impl<'a> TabsState<'a> {
             "Regular Transfers" => Some(TransactionTab::Regular),
             "ERC20 Transfers" => Some(TransactionTab::ERC20),
             "ERC721 Transfers" => Some(TransactionTab::ERC721),
+            "ERC1155 Transfers" => Some(TransactionTab::ERC1155),
+            "Unknown Transfers" => Some(TransactionTab::Unknown),
             _ => None,
         }
     }
@@ -115,53 +162,66 @@ pub enum TransactionTab {
     Regular,
     ERC20,
     ERC721,
+    ERC1155,
+    Unknown,
 }
 pub struct App<'a> {
     pub current_screen: CurrentScreen,
     pub is_exiting: bool,
-    pub is_saving_json: bool,
+    pub is_exporting: bool,
+    pub export_format: ExportFormat,
+    pub export_path: String,
+    pub is_confirming_overwrite: bool,
+    pub toast: Option<Toast>,
+    pub is_cancelling: bool,
+    pub is_editing_label: bool,
+    pub is_searching: bool,
+    pub is_saving_query: bool,
+    pub is_selecting_chain: bool,
     pub currently_editing: bool,
+    pub show_local_time: bool,
     pub query: WalletQuery,
     pub transaction_tabs: TabsState<'a>,
     pub table_states: TableStates,
     pub query_state: ListState,
     pub scrollbar_states: ScrollbarStates,
     pub transfers: Transfers,
-}
-
-#[derive(Serialize)]
-pub struct RegularTransfer {
-    pub hash: String,
-    pub block_hash: String,
-    pub block: String,
-    pub nonce: String,
-    pub to: String,
-    pub from: String,
-    pub value: String,
-    pub gas_used: String,
-}
-
-#[derive(Serialize)]
-pub struct Transfers {
-    pub regular_transfers: Vec<RegularTransfer>,
-    pub erc20_transfers: Vec<Erc20Transfer>,
-    pub erc721_transfers: Vec<Erc721Transfer>,
-}
-
-impl Transfers {
-    pub fn new() -> Self {
-        Transfers {
-            regular_transfers: Vec::new(),
-            erc20_transfers: Vec::new(),
-            erc721_transfers: Vec::new(),
-        }
-    }
+    pub query_task: Option<JoinHandle<()>>,
+    pub query_receiver: Option<UnboundedReceiver<QueryEvent>>,
+    pub query_progress: QueryProgress,
+    pub partial_to_block: Option<u64>,
+    pub query_error: Option<QueryError>,
+    pub resolved_dates: HashMap<String, u64>,
+    pub date_receiver: Option<UnboundedReceiver<(String, u64)>>,
+    pub tokens: TokenRegistry,
+    pub token_sender: UnboundedSender<(String, Option<TokenMetadata>)>,
+    pub token_receiver: UnboundedReceiver<(String, Option<TokenMetadata>)>,
+    pub address_book: AddressBook,
+    pub ens: EnsNames,
+    pub ens_sender: UnboundedSender<(String, Option<String>)>,
+    pub ens_receiver: UnboundedReceiver<(String, Option<String>)>,
+    pub name_receiver: Option<UnboundedReceiver<(String, Option<String>)>>,
+    pub label_address: String,
+    pub label_input: String,
+    pub label_error: Option<String>,
+    pub search_input: String,
+    pub search_error: Option<String>,
+    pub history: History,
+    pub history_state: TableState,
+    pub history_error: Option<String>,
+    pub config: Config,
+    pub saved_query_state: ListState,
+    pub query_name_input: String,
+    pub query_name_error: Option<String>,
+    pub chain_state: ListState,
 }
 
 pub struct TableStates {
     pub regular_table: TableState,
     pub erc20_table: TableState,
     pub erc721_table: TableState,
+    pub erc1155_table: TableState,
+    pub unknown_table: TableState,
 }
 
 impl TableStates {
@@ -170,6 +230,8 @@ impl TableStates {
             regular_table: TableState::default().with_selected(0),
             erc20_table: TableState::default().with_selected(0),
             erc721_table: TableState::default().with_selected(0),
+            erc1155_table: TableState::default().with_selected(0),
+            unknown_table: TableState::default().with_selected(0),
         }
     }
 }
@@ -178,6 +240,8 @@ pub struct ScrollbarStates {
     pub regular_scrollbar: ScrollbarState,
     pub erc20_scrollbar: ScrollbarState,
     pub erc721_scrollbar: ScrollbarState,
+    pub erc1155_scrollbar: ScrollbarState,
+    pub unknown_scrollbar: ScrollbarState,
 }
 
 impl ScrollbarStates {
@@ -186,42 +250,617 @@ impl ScrollbarStates {
             regular_scrollbar: ScrollbarState::new(0),
             erc20_scrollbar: ScrollbarState::new(0),
             erc721_scrollbar: ScrollbarState::new(0),
+            erc1155_scrollbar: ScrollbarState::new(0),
+            unknown_scrollbar: ScrollbarState::new(0),
         }
     }
 }
 
 impl<'a> App<'a> {
     pub fn new() -> Self {
+        let (token_sender, token_receiver) = mpsc::unbounded_channel();
+        let (ens_sender, ens_receiver) = mpsc::unbounded_channel();
         App {
             current_screen: CurrentScreen::Startup,
             is_exiting: false,
-            is_saving_json: false,
+            is_exporting: false,
+            export_format: ExportFormat::Json,
+            export_path: String::new(),
+            is_confirming_overwrite: false,
+            toast: None,
+            is_cancelling: false,
+            is_editing_label: false,
+            is_searching: false,
+            is_saving_query: false,
+            is_selecting_chain: false,
             currently_editing: false,
+            show_local_time: false,
             transaction_tabs: TabsState::new(vec![
                 "Regular Transfers",
                 "ERC20 Transfers",
                 "ERC721 Transfers",
+                "ERC1155 Transfers",
+                "Unknown Transfers",
             ]),
             table_states: TableStates::new(),
             scrollbar_states: ScrollbarStates::new(),
             query: WalletQuery::new(),
             query_state: ListState::default().with_selected(Some(0)),
             transfers: Transfers::new(),
+            query_task: None,
+            query_receiver: None,
+            query_progress: QueryProgress::new(0),
+            partial_to_block: None,
+            query_error: None,
+            resolved_dates: HashMap::new(),
+            date_receiver: None,
+            tokens: TokenRegistry::new(1),
+            token_sender,
+            token_receiver,
+            address_book: AddressBook::new(),
+            ens: EnsNames::new(),
+            ens_sender,
+            ens_receiver,
+            name_receiver: None,
+            label_address: String::new(),
+            label_input: String::new(),
+            label_error: None,
+            search_input: String::new(),
+            search_error: None,
+            history: History::new(),
+            history_state: TableState::default().with_selected(0),
+            history_error: None,
+            config: Config::new(),
+            saved_query_state: ListState::default().with_selected(Some(0)),
+            query_name_input: String::new(),
+            query_name_error: None,
+            chain_state: ListState::default(),
         }
     }
 
-    pub fn set_scrollbar_states(&mut self) {
-        if !&self.transfers.regular_transfers.is_empty() {
-            self.scrollbar_states.regular_scrollbar =
-                ScrollbarState::new(&self.transfers.regular_transfers.len() - 1);
+    pub fn receive_query_events(&mut self) {
+        if let Some(mut receiver) = self.query_receiver.take() {
+            let finished = loop {
+                match receiver.try_recv() {
+                    Ok(QueryEvent::Finished) | Err(TryRecvError::Disconnected) => break true,
+                    Ok(QueryEvent::Failed(err)) => {
+                        self.query_error = Some(err);
+                        break true;
+                    }
+                    Ok(event) => self.apply_query_event(event),
+                    Err(TryRecvError::Empty) => break false,
+                }
+            };
+            if finished {
+                self.query_task = None;
+                let outcome = match &self.query_error {
+                    Some(err) => QueryOutcome::Failed(err.to_string()),
+                    None => {
+                        self.save_results();
+                        QueryOutcome::Completed
+                    }
+                };
+                self.record_history(outcome);
+                self.current_screen = match self.query_error {
+                    Some(_) => CurrentScreen::Error,
+                    None => CurrentScreen::Main,
+                };
+            } else {
+                self.query_receiver = Some(receiver);
+            }
+            self.set_scrollbar_states();
+        }
+    }
+
+    // Stores the results so the next query of these wallets only scans newer blocks
+    fn save_results(&self) {
+        // The cache only saves scanning time, so a failed write is not reported
+        let _ = cache::save_results(
+            self.query.chain.id,
+            &self.query.address_list(),
+            self.query_progress.from_block,
+            self.query_progress.next_block,
+            TransferKinds::of(&self.query),
+            &self.transfers,
+        );
+    }
+
+    pub fn receive_resolved_dates(&mut self) {
+        if let Some(receiver) = self.date_receiver.as_mut() {
+            loop {
+                match receiver.try_recv() {
+                    Ok((date, block)) => {
+                        self.resolved_dates.insert(date, block);
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.date_receiver = None;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn receive_token_metadata(&mut self) {
+        let mut received = false;
+        while let Ok((contract, metadata)) = self.token_receiver.try_recv() {
+            self.tokens.resolve(contract, metadata);
+            received = true;
+        }
+        if received && !self.tokens.is_resolving() {
+            // The cache only saves RPC round trips, so a failed write is not reported
+            let _ = self.tokens.save_cache();
+        }
+    }
+
+    pub fn receive_ens_names(&mut self) {
+        let mut received = false;
+        while let Ok((address, name)) = self.ens_receiver.try_recv() {
+            self.ens.resolve(address, name);
+            received = true;
+        }
+        if received && !self.ens.is_resolving() {
+            let _ = self.ens.save_cache();
+        }
+    }
+
+    // Addresses of ENS names typed in the query builder, names that don't resolve stay unset
+    pub fn receive_ens_addresses(&mut self) {
+        if let Some(receiver) = self.name_receiver.as_mut() {
+            loop {
+                match receiver.try_recv() {
+                    Ok((name, Some(address))) => {
+                        self.query.ens_addresses.insert(name, address);
+                    }
+                    Ok((_, None)) => {}
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        self.name_receiver = None;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // Looks up metadata for token contracts that are not in the token list or cache
+    pub fn resolve_tokens(&mut self, transfers: &Transfers) {
+        if let Some(rpc_url) = tokens::rpc_url(&self.query.chain) {
+            let contracts = self.tokens.unresolved(
+                transfers
+                    .erc20_transfers
+                    .iter()
+                    .map(|t| &t.contract)
+                    .chain(transfers.erc721_transfers.iter().map(|t| &t.contract))
+                    .chain(transfers.erc1155_transfers.iter().map(|t| &t.contract)),
+            );
+            if !contracts.is_empty() {
+                tokio::spawn(tokens::resolve_tokens(
+                    rpc_url,
+                    contracts,
+                    self.token_sender.clone(),
+                ));
+            }
+        }
+    }
+
+    // Looks up primary ENS names of the counterparties that are not in the cache or snapshot
+    pub fn lookup_ens_names(&mut self, transfers: &Transfers) {
+        if let Some(rpc_url) = ens::rpc_url() {
+            let addresses = self.ens.unresolved(transfers.addresses());
+            if !addresses.is_empty() {
+                tokio::spawn(ens::lookup_addresses(
+                    rpc_url,
+                    addresses,
+                    self.ens_sender.clone(),
+                ));
+            }
+        }
+    }
+
+    // Amount scaled by the token decimals with its symbol, or the raw amount when unknown
+    pub fn erc20_amount(&self, transfer: &Erc20Transfer) -> String {
+        self.tokens
+            .get(&transfer.contract)
+            .and_then(|token| token.format_amount(&transfer.amount))
+            .unwrap_or_else(|| transfer.amount.clone())
+    }
+
+    // From and to of every row in the current tab, empty where a transfer has none
+    fn tab_addresses(&self) -> Vec<(String, String)> {
+        match self.transaction_tabs.selected() {
+            Some(TransactionTab::Regular) => self
+                .transfers
+                .regular_transfers
+                .iter()
+                .map(|t| (t.from.clone(), t.to.clone()))
+                .collect(),
+            Some(TransactionTab::ERC20) => self
+                .transfers
+                .erc20_transfers
+                .iter()
+                .map(|t| (t.from.clone(), t.to.clone()))
+                .collect(),
+            Some(TransactionTab::ERC721) => self
+                .transfers
+                .erc721_transfers
+                .iter()
+                .map(|t| (t.from.clone(), t.to.clone()))
+                .collect(),
+            Some(TransactionTab::ERC1155) => self
+                .transfers
+                .erc1155_transfers
+                .iter()
+                .map(|t| (t.from.clone(), t.to.clone()))
+                .collect(),
+            Some(TransactionTab::Unknown) => self
+                .transfers
+                .unknown_transfers
+                .iter()
+                .map(|t| {
+                    let mut addresses = t.indexed_addresses().into_iter();
+                    let from = addresses.next().unwrap_or_default();
+                    (from, addresses.next().unwrap_or_default())
+                })
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn selected_row(&self) -> Option<usize> {
+        match self.transaction_tabs.selected()? {
+            TransactionTab::Regular => self.table_states.regular_table.selected(),
+            TransactionTab::ERC20 => self.table_states.erc20_table.selected(),
+            TransactionTab::ERC721 => self.table_states.erc721_table.selected(),
+            TransactionTab::ERC1155 => self.table_states.erc1155_table.selected(),
+            TransactionTab::Unknown => self.table_states.unknown_table.selected(),
+        }
+    }
+
+    fn select_row(&mut self, i: usize) {
+        match self.transaction_tabs.selected() {
+            Some(TransactionTab::Regular) => {
+                self.table_states.regular_table.select(Some(i));
+                self.scrollbar_states.regular_scrollbar = self
+                    .scrollbar_states
+                    .regular_scrollbar
+                    .position(i * LINE_HEIGHT);
+            }
+            Some(TransactionTab::ERC20) => {
+                self.table_states.erc20_table.select(Some(i));
+                self.scrollbar_states.erc20_scrollbar = self
+                    .scrollbar_states
+                    .erc20_scrollbar
+                    .position(i * LINE_HEIGHT);
+            }
+            Some(TransactionTab::ERC721) => {
+                self.table_states.erc721_table.select(Some(i));
+                self.scrollbar_states.erc721_scrollbar = self
+                    .scrollbar_states
+                    .erc721_scrollbar
+                    .position(i * LINE_HEIGHT);
+            }
+            Some(TransactionTab::ERC1155) => {
+                self.table_states.erc1155_table.select(Some(i));
+                self.scrollbar_states.erc1155_scrollbar = self
+                    .scrollbar_states
+                    .erc1155_scrollbar
+                    .position(i * LINE_HEIGHT);
+            }
+            Some(TransactionTab::Unknown) => {
+                self.table_states.unknown_table.select(Some(i));
+                self.scrollbar_states.unknown_scrollbar = self
+                    .scrollbar_states
+                    .unknown_scrollbar
+                    .position(i * LINE_HEIGHT);
+            }
+            None => {}
+        }
+    }
+
+    // The other side of the selected transfer, or the recipient when both sides are queried
+    fn selected_counterparty(&self) -> Option<String> {
+        let addresses = self.tab_addresses();
+        let (from, to) = addresses.get(self.selected_row()?)?;
+        let counterparty = match self.query.address_list().contains(&from.to_lowercase()) {
+            true => to,
+            false => from,
+        };
+        Some(counterparty.clone()).filter(|address| !address.is_empty())
+    }
+
+    pub fn start_label_edit(&mut self) {
+        if let Some(address) = self.selected_counterparty() {
+            self.label_input = self
+                .address_book
+                .label(&address)
+                .unwrap_or_default()
+                .to_string();
+            self.label_address = address;
+            self.label_error = None;
+            self.is_editing_label = true;
+        }
+    }
+
+    pub fn save_label(&mut self) {
+        match self
+            .address_book
+            .set_label(&self.label_address, &self.label_input)
+        {
+            Ok(()) => self.is_editing_label = false,
+            Err(err) => self.label_error = Some(err),
+        }
+    }
+
+    // Selects the next row in the current tab whose from or to address or label matches
+    pub fn search_next(&mut self) -> bool {
+        if self.search_input.is_empty() {
+            return false;
+        }
+        let addresses = self.tab_addresses();
+        let start = self.selected_row().map_or(0, |i| i + 1);
+        let found = (0..addresses.len())
+            .map(|offset| (start + offset) % addresses.len())
+            .find(|&i| {
+                let (from, to) = &addresses[i];
+                self.matches(from) || self.matches(to)
+            });
+        match found {
+            Some(i) => {
+                self.select_row(i);
+                self.search_error = None;
+                true
+            }
+            None => {
+                self.search_error = Some(format!("No matches for '{}'", self.search_input));
+                false
+            }
+        }
+    }
+
+    // Case insensitive match against an address, its label and its ENS name
+    fn matches(&self, address: &str) -> bool {
+        self.address_book.matches(address, &self.search_input)
+            || self.ens.name(address).is_some_and(|name| {
+                name.to_lowercase()
+                    .contains(&self.search_input.to_lowercase())
+            })
+    }
+
+    pub fn cancel_query(&mut self) {
+        if let Some(task) = self.query_task.take() {
+            task.abort();
+        }
+
+        let mut finished = false;
+        if let Some(mut receiver) = self.query_receiver.take() {
+            // Batches sent before the abort are still buffered in the channel
+            receiver.close();
+            while let Ok(event) = receiver.try_recv() {
+                match event {
+                    QueryEvent::Finished => finished = true,
+                    QueryEvent::Failed(err) => {
+                        self.query_error = Some(err);
+                        finished = true;
+                    }
+                    event => self.apply_query_event(event),
+                }
+            }
+        }
+        self.set_scrollbar_states();
+
+        // A query that ended before the cancel is reported as it ended
+        if let Some(err) = &self.query_error {
+            self.record_history(QueryOutcome::Failed(err.to_string()));
+            self.current_screen = CurrentScreen::Error;
+        } else if finished {
+            self.save_results();
+            self.record_history(QueryOutcome::Completed);
+            self.current_screen = CurrentScreen::Main;
+        } else {
+            // The blocks scanned before the cancel are cached up to the last batch received
+            if self.query_progress.is_resolved {
+                self.save_results();
+            }
+            self.record_history(QueryOutcome::Cancelled);
+            self.is_cancelling = true;
         }
-        if !&self.transfers.erc20_transfers.is_empty() {
-            self.scrollbar_states.erc20_scrollbar =
-                ScrollbarState::new(&self.transfers.erc20_transfers.len() - 1);
+    }
+
+    fn record_history(&mut self, outcome: QueryOutcome) {
+        let entry = HistoryEntry {
+            query: self.query.clone(),
+            executed_at: Utc::now().timestamp(),
+            duration_ms: self.query_progress.elapsed().as_millis() as u64,
+            from_block: self.query_progress.from_block,
+            next_block: self.query_progress.next_block,
+            rows: RowCounts::of(&self.transfers),
+            outcome,
+        };
+        // History is a convenience, a failed write should not end the query
+        let _ = self.history.record(entry);
+    }
+
+    // Opens the saved query selected on the startup screen in the query builder
+    pub fn load_saved_query(&mut self) {
+        let saved = self
+            .saved_query_state
+            .selected()
+            .and_then(|i| self.config.saved_query(i));
+        if let Some(query) = saved {
+            self.query = query;
+            self.resolved_dates.clear();
+            self.current_screen = CurrentScreen::QueryBuilder;
+        }
+    }
+
+    pub fn start_chain_select(&mut self) {
+        let selected = self
+            .config
+            .chains()
+            .iter()
+            .position(|chain| chain.name == self.query.chain.name);
+        self.chain_state.select(Some(selected.unwrap_or(0)));
+        self.is_selecting_chain = true;
+    }
+
+    pub fn select_chain(&mut self) {
+        let chain = self
+            .chain_state
+            .selected()
+            .and_then(|i| self.config.chains().get(i))
+            .cloned();
+        if let Some(chain) = chain {
+            self.resolved_dates.clear();
+            self.query.chain = chain;
+        }
+        self.is_selecting_chain = false;
+    }
+
+    pub fn save_query(&mut self) {
+        match self.config.save_query(&self.query_name_input, &self.query) {
+            Ok(()) => self.is_saving_query = false,
+            Err(err) => self.query_name_error = Some(err),
         }
-        if !&self.transfers.erc721_transfers.is_empty() {
-            self.scrollbar_states.erc721_scrollbar =
-                ScrollbarState::new(&self.transfers.erc721_transfers.len() - 1);
+    }
+
+    // Opens the export popup with a timestamped path in the outputs folder. Only the first
+    // wallet is named, so the file name stays short however many wallets were queried
+    pub fn start_export(&mut self) {
+        let addresses = self.query.address_list();
+        let wallets = match addresses.as_slice() {
+            [] => String::new(),
+            [address] => address.clone(),
+            [address, rest @ ..] => format!("{}+{}", address, rest.len()),
+        };
+        let stem = format!(
+            "outputs/{}-{}-{}",
+            wallets,
+            self.query.chain.slug(),
+            Local::now().format("%Y%m%d-%H%M%S")
+        );
+        self.export_path = self.export_format.file_name(&stem);
+        self.is_confirming_overwrite = false;
+        self.is_exporting = true;
+    }
+
+    // Switches the format, keeping the path but for its extension
+    pub fn select_export_format(&mut self, format: ExportFormat) {
+        let stem = ExportFormat::strip_extension(&self.export_path);
+        self.export_path = format.file_name(stem);
+        self.export_format = format;
+    }
+
+    // Writes the export, asking before replacing an existing file
+    pub fn export(&mut self) {
+        let path = Path::new(self.export_path.trim());
+        if path.exists() && !self.is_confirming_overwrite {
+            self.is_confirming_overwrite = true;
+            return;
+        }
+        let result = export::write_file(
+            path,
+            self.export_format,
+            &self.transfers,
+            &self.address_book,
+        );
+        let (message, is_error) = match result {
+            Ok(()) => (
+                format!("Wrote {} rows to {}", self.num_transfers(), path.display()),
+                false,
+            ),
+            Err(err) => (format!("Could not write {}: {}", path.display(), err), true),
+        };
+        self.toast = Some(Toast {
+            message,
+            is_error,
+            shown_at: Instant::now(),
+        });
+        self.is_confirming_overwrite = false;
+        self.is_exporting = false;
+    }
+
+    pub fn toast(&self) -> Option<&Toast> {
+        self.toast
+            .as_ref()
+            .filter(|toast| toast.shown_at.elapsed() < TOAST_DURATION)
+    }
+
+    pub fn selected_history_entry(&self) -> Option<&HistoryEntry> {
+        self.history.recent().nth(self.history_state.selected()?)
+    }
+
+    pub fn next_history_row(&mut self) {
+        let i = match self.history_state.selected() {
+            Some(i) if i + 1 < self.history.len() => i + 1,
+            _ => 0,
+        };
+        self.history_state.select(Some(i));
+    }
+
+    pub fn previous_history_row(&mut self) {
+        let i = match self.history_state.selected() {
+            Some(0) | None => self.history.len().saturating_sub(1),
+            Some(i) => i - 1,
+        };
+        self.history_state.select(Some(i));
+    }
+
+    fn apply_query_event(&mut self, event: QueryEvent) {
+        match event {
+            QueryEvent::Batch(batch) => {
+                self.query_progress.from_block = batch.from_block;
+                self.query_progress.to_block = batch.to_block;
+                self.query_progress.next_block = batch.next_block;
+                self.query_progress.archive_height = batch.archive_height;
+                self.query_progress.is_resolved = true;
+                // Only the new transfers are looked up, those of earlier batches already were
+                self.resolve_tokens(&batch.transfers);
+                self.lookup_ens_names(&batch.transfers);
+                self.transfers.extend(batch.transfers);
+            }
+            QueryEvent::Failed(err) => self.query_error = Some(err),
+            QueryEvent::Finished => {}
+        }
+    }
+
+    pub fn num_transfers(&self) -> usize {
+        self.transfers.regular_transfers.len()
+            + self.transfers.erc20_transfers.len()
+            + self.transfers.erc721_transfers.len()
+            + self.transfers.erc1155_transfers.len()
+            + self.transfers.unknown_transfers.len()
+    }
+
+    // Keeps the scrollbar positions, as the tables grow while a query runs
+    pub fn set_scrollbar_states(&mut self) {
+        let lengths = [
+            (
+                &mut self.scrollbar_states.regular_scrollbar,
+                self.transfers.regular_transfers.len(),
+            ),
+            (
+                &mut self.scrollbar_states.erc20_scrollbar,
+                self.transfers.erc20_transfers.len(),
+            ),
+            (
+                &mut self.scrollbar_states.erc721_scrollbar,
+                self.transfers.erc721_transfers.len(),
+            ),
+            (
+                &mut self.scrollbar_states.erc1155_scrollbar,
+                self.transfers.erc1155_transfers.len(),
+            ),
+            (
+                &mut self.scrollbar_states.unknown_scrollbar,
+                self.transfers.unknown_transfers.len(),
+            ),
+        ];
+        for (scrollbar, len) in lengths {
+            if len > 0 {
+                *scrollbar = scrollbar.content_length(len - 1);
+            }
         }
     }
 
@@ -285,6 +924,44 @@ impl<'a> App<'a> {
                             .position(i * LINE_HEIGHT);
                     }
                 }
+                TransactionTab::ERC1155 => {
+                    if !self.transfers.erc1155_transfers.is_empty() {
+                        let i = match self.table_states.erc1155_table.selected() {
+                            Some(i) => {
+                                if i >= self.transfers.erc1155_transfers.len() - 1 {
+                                    0
+                                } else {
+                                    i + 1
+                                }
+                            }
+                            None => 0,
+                        };
+                        self.table_states.erc1155_table.select(Some(i));
+                        self.scrollbar_states.erc1155_scrollbar = self
+                            .scrollbar_states
+                            .erc1155_scrollbar
+                            .position(i * LINE_HEIGHT);
+                    }
+                }
+                TransactionTab::Unknown => {
+                    if !self.transfers.unknown_transfers.is_empty() {
+                        let i = match self.table_states.unknown_table.selected() {
+                            Some(i) => {
+                                if i >= self.transfers.unknown_transfers.len() - 1 {
+                                    0
+                                } else {
+                                    i + 1
+                                }
+                            }
+                            None => 0,
+                        };
+                        self.table_states.unknown_table.select(Some(i));
+                        self.scrollbar_states.unknown_scrollbar = self
+                            .scrollbar_states
+                            .unknown_scrollbar
+                            .position(i * LINE_HEIGHT);
+                    }
+                }
             }
         }
     }
@@ -349,6 +1026,44 @@ impl<'a> App<'a> {
                             .position(i * LINE_HEIGHT);
                     }
                 }
+                TransactionTab::ERC1155 => {
+                    if !self.transfers.erc1155_transfers.is_empty() {
+                        let i = match self.table_states.erc1155_table.selected() {
+                            Some(i) => {
+                                if i == 0 {
+                                    self.transfers.erc1155_transfers.len() - 1
+                                } else {
+                                    i - 1
+                                }
+                            }
+                            None => 0,
+                        };
+                        self.table_states.erc1155_table.select(Some(i));
+                        self.scrollbar_states.erc1155_scrollbar = self
+                            .scrollbar_states
+                            .erc1155_scrollbar
+                            .position(i * LINE_HEIGHT);
+                    }
+                }
+                TransactionTab::Unknown => {
+                    if !self.transfers.unknown_transfers.is_empty() {
+                        let i = match self.table_states.unknown_table.selected() {
+                            Some(i) => {
+                                if i == 0 {
+                                    self.transfers.unknown_transfers.len() - 1
+                                } else {
+                                    i - 1
+                                }
+                            }
+                            None => 0,
+                        };
+                        self.table_states.unknown_table.select(Some(i));
+                        self.scrollbar_states.unknown_scrollbar = self
+                            .scrollbar_states
+                            .unknown_scrollbar
+                            .position(i * LINE_HEIGHT);
+                    }
+                }
             }
         }
     }
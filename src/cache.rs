@@ -0,0 +1,683 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+
+use crate::{query::WalletQuery, transfers::Transfers};
+
+// Transfer types toggled in a query, unknown transfers come with ERC20 and ERC721. ERC721 scans
+// also keep the unindexed contracts they read, sorted.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TransferKinds {
+    pub regular: bool,
+    pub erc20: bool,
+    pub erc721: bool,
+    pub erc1155: bool,
+    #[serde(default)]
+    pub unindexed_contracts: Vec<String>,
+}
+
+impl TransferKinds {
+    pub fn of(query: &WalletQuery) -> Self {
+        TransferKinds {
+            regular: query.regular_transfers,
+            erc20: query.erc20_transfers,
+            erc721: query.erc721_transfers,
+            erc1155: query.erc1155_transfers,
+            unindexed_contracts: match query.erc721_transfers {
+                true => {
+                    let mut contracts: Vec<String> = query
+                        .unindexed_contracts
+                        .iter()
+                        .map(|contract| contract.to_lowercase())
+                        .collect();
+                    contracts.sort();
+                    contracts.dedup();
+                    contracts
+                }
+                false => Vec::new(),
+            },
+        }
+    }
+
+    fn covers(&self, other: &TransferKinds) -> bool {
+        (self.regular || !other.regular)
+            && (self.erc20 || !other.erc20)
+            && (self.erc721 || !other.erc721)
+            && (self.erc1155 || !other.erc1155)
+            && other
+                .unindexed_contracts
+                .iter()
+                .all(|contract| self.unindexed_contracts.contains(contract))
+    }
+
+    fn retain(&self, transfers: &mut Transfers) {
+        if !self.regular {
+            transfers.regular_transfers.clear();
+        }
+        if !self.erc20 {
+            transfers.erc20_transfers.clear();
+        }
+        if !self.erc721 {
+            transfers.erc721_transfers.clear();
+        }
+        if !self.erc1155 {
+            transfers.erc1155_transfers.clear();
+        }
+        if !self.erc20 && !self.erc721 {
+            transfers.unknown_transfers.clear();
+        }
+    }
+
+    // Kinds as flags and a comma separated list of contracts in the database, so ranges are
+    // picked without reading their transfers
+    fn bits(&self) -> u8 {
+        [self.regular, self.erc20, self.erc721, self.erc1155]
+            .into_iter()
+            .enumerate()
+            .fold(0, |bits, (i, kind)| bits | (kind as u8) << i)
+    }
+
+    fn contracts(&self) -> String {
+        self.unindexed_contracts.join(",")
+    }
+
+    fn from_columns(bits: u8, contracts: &str) -> Self {
+        TransferKinds {
+            regular: bits & 1 != 0,
+            erc20: bits & 2 != 0,
+            erc721: bits & 4 != 0,
+            erc1155: bits & 8 != 0,
+            unindexed_contracts: contracts
+                .split(',')
+                .filter(|contract| !contract.is_empty())
+                .map(|contract| contract.to_string())
+                .collect(),
+        }
+    }
+}
+
+// One scan of a wallet on one chain, from from_block up to but excluding next_block, stored
+// in a row of its own so saving a scan never rewrites earlier ones
+#[derive(Clone, Debug, PartialEq)]
+struct CachedRange {
+    from_block: u64,
+    next_block: u64,
+    kinds: TransferKinds,
+}
+
+impl CachedRange {
+    // A newer scan replaces the ranges it fully contains
+    fn is_superseded_by(&self, other: &CachedRange) -> bool {
+        other.kinds.covers(&self.kinds)
+            && other.from_block <= self.from_block
+            && other.next_block >= self.next_block
+    }
+}
+
+// How long a query waits for another one saving results to the same database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("hypertui").join("transfers.db"))
+}
+
+// Scans are stored as rows of the ranges table, with the transfers of the wallet as JSON
+fn open(path: &Path) -> rusqlite::Result<Connection> {
+    let connection = Connection::open(path)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ranges (
+            chain_id INTEGER NOT NULL,
+            address TEXT NOT NULL,
+            from_block INTEGER NOT NULL,
+            next_block INTEGER NOT NULL,
+            kinds INTEGER NOT NULL,
+            unindexed_contracts TEXT NOT NULL,
+            transfers TEXT NOT NULL,
+            PRIMARY KEY (chain_id, address, from_block, next_block, kinds, unindexed_contracts)
+        )",
+    )?;
+    Ok(connection)
+}
+
+// A database that can't be read only means blocks are scanned again
+fn cached_ranges(connection: &Connection, chain_id: u64, address: &str) -> Vec<CachedRange> {
+    let ranges = connection
+        .prepare(
+            "SELECT from_block, next_block, kinds, unindexed_contracts FROM ranges
+            WHERE chain_id = ?1 AND address = ?2",
+        )
+        .and_then(|mut statement| {
+            statement
+                .query_map(params![chain_id, address.to_lowercase()], |row| {
+                    Ok(CachedRange {
+                        from_block: row.get(0)?,
+                        next_block: row.get(1)?,
+                        kinds: TransferKinds::from_columns(row.get(2)?, &row.get::<_, String>(3)?),
+                    })
+                })?
+                .collect()
+        });
+    ranges.unwrap_or_default()
+}
+
+// Ranges that cover the blocks from start_block on without gaps, each with the blocks used
+// from it. Where ranges overlap, the one reaching furthest is taken.
+fn select_ranges(
+    ranges: &[CachedRange],
+    start_block: u64,
+    kinds: &TransferKinds,
+) -> Vec<(CachedRange, u64)> {
+    let mut selected = Vec::new();
+    let mut block = start_block;
+    while let Some(range) = ranges
+        .iter()
+        .filter(|range| {
+            range.kinds.covers(kinds) && range.from_block <= block && range.next_block > block
+        })
+        .max_by_key(|range| range.next_block)
+    {
+        selected.push((range.clone(), block));
+        block = range.next_block;
+    }
+    selected
+}
+
+fn read_range(
+    connection: &Connection,
+    chain_id: u64,
+    address: &str,
+    range: &CachedRange,
+) -> Option<Transfers> {
+    let content: String = connection
+        .query_row(
+            "SELECT transfers FROM ranges WHERE chain_id = ?1 AND address = ?2
+            AND from_block = ?3 AND next_block = ?4 AND kinds = ?5 AND unindexed_contracts = ?6",
+            params![
+                chain_id,
+                address.to_lowercase(),
+                range.from_block,
+                range.next_block,
+                range.kinds.bits(),
+                range.kinds.contracts()
+            ],
+            |row| row.get(0),
+        )
+        .optional()
+        .ok()??;
+    serde_json::from_str(&content).ok()
+}
+
+// Block to resume a query at, and the cached transfers of the queried wallets before it.
+// The earliest wallet cache decides, so no wallet misses blocks.
+pub fn load_cached(
+    chain_id: u64,
+    addresses: &[String],
+    start_block: u64,
+    end_block: Option<u64>,
+    kinds: TransferKinds,
+) -> (u64, Transfers) {
+    match cache_path().filter(|path| path.exists()) {
+        Some(path) => load_cached_in(&path, chain_id, addresses, start_block, end_block, kinds),
+        None => (start_block, Transfers::new()),
+    }
+}
+
+fn load_cached_in(
+    path: &Path,
+    chain_id: u64,
+    addresses: &[String],
+    start_block: u64,
+    end_block: Option<u64>,
+    kinds: TransferKinds,
+) -> (u64, Transfers) {
+    let Ok(connection) = open(path) else {
+        return (start_block, Transfers::new());
+    };
+    // Transfers of each wallet, as (transfers, first block, next block) per range read
+    let scans: Vec<Vec<(Transfers, u64, u64)>> = addresses
+        .iter()
+        .map(|address| {
+            let ranges = cached_ranges(&connection, chain_id, address);
+            // An unreadable range ends the cover, the blocks after it are scanned again
+            select_ranges(&ranges, start_block, &kinds)
+                .into_iter()
+                .map_while(|(range, from_block)| {
+                    read_range(&connection, chain_id, address, &range)
+                        .map(|transfers| (transfers, from_block, range.next_block))
+                })
+                .collect()
+        })
+        .collect();
+    let resume_block = scans
+        .iter()
+        .map(|ranges| ranges.last().map_or(start_block, |range| range.2))
+        .min()
+        .unwrap_or(start_block);
+    // HyperSync's to_block is exclusive, the query's end block inclusive
+    let resume_block = match end_block {
+        Some(end_block) => resume_block.min(end_block + 1),
+        None => resume_block,
+    };
+
+    let mut transfers = Transfers::new();
+    for (i, ranges) in scans.into_iter().enumerate() {
+        // Transfers between two queried wallets are in both caches, take the first
+        let earlier = &addresses[..i];
+        for (mut scanned, from_block, next_block) in ranges {
+            kinds.retain(&mut scanned);
+            scanned.retain(|block, parties| {
+                block >= from_block
+                    && block < next_block.min(resume_block)
+                    && !parties
+                        .iter()
+                        .any(|party| earlier.contains(&party.to_lowercase()))
+            });
+            transfers.extend(scanned);
+        }
+    }
+    (resume_block, transfers)
+}
+
+// Stores the transfers of a scan from from_block up to next_block as a new range in the cache
+// of each wallet, replacing the cached ranges it contains. Cancelled queries store the blocks
+// they got through.
+pub fn save_results(
+    chain_id: u64,
+    addresses: &[String],
+    from_block: u64,
+    next_block: u64,
+    kinds: TransferKinds,
+    transfers: &Transfers,
+) -> Result<(), String> {
+    let path = cache_path().ok_or("no cache directory")?;
+    save_results_in(
+        &path, chain_id, addresses, from_block, next_block, kinds, transfers,
+    )
+}
+
+// Each save is one transaction, so queries of the same wallets saving at the same time wait
+// for each other and never see the ranges of the other half replaced
+fn save_results_in(
+    path: &Path,
+    chain_id: u64,
+    addresses: &[String],
+    from_block: u64,
+    next_block: u64,
+    kinds: TransferKinds,
+    transfers: &Transfers,
+) -> Result<(), String> {
+    if next_block <= from_block {
+        return Ok(());
+    }
+    let range = CachedRange {
+        from_block,
+        next_block,
+        kinds,
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+    }
+    let mut connection = open(path).map_err(|err| err.to_string())?;
+    let transaction = connection
+        .transaction_with_behavior(TransactionBehavior::Immediate)
+        .map_err(|err| err.to_string())?;
+    for address in addresses {
+        let address = address.to_lowercase();
+        let mut scanned = transfers.clone();
+        scanned.retain(|_, parties| {
+            parties
+                .iter()
+                .any(|party| party.eq_ignore_ascii_case(&address))
+        });
+        let content = serde_json::to_string(&scanned).map_err(|err| err.to_string())?;
+
+        for cached in cached_ranges(&transaction, chain_id, &address) {
+            if cached.is_superseded_by(&range) {
+                transaction
+                    .execute(
+                        "DELETE FROM ranges WHERE chain_id = ?1 AND address = ?2
+                        AND from_block = ?3 AND next_block = ?4 AND kinds = ?5
+                        AND unindexed_contracts = ?6",
+                        params![
+                            chain_id,
+                            address,
+                            cached.from_block,
+                            cached.next_block,
+                            cached.kinds.bits(),
+                            cached.kinds.contracts()
+                        ],
+                    )
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+        transaction
+            .execute(
+                "INSERT INTO ranges
+                (chain_id, address, from_block, next_block, kinds, unindexed_contracts, transfers)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    chain_id,
+                    address,
+                    range.from_block,
+                    range.next_block,
+                    range.kinds.bits(),
+                    range.kinds.contracts(),
+                    content
+                ],
+            )
+            .map_err(|err| err.to_string())?;
+    }
+    transaction.commit().map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process, thread};
+
+    use super::*;
+    use crate::transfers::RegularTransfer;
+
+    const ALICE: &str = "0x1111111111111111111111111111111111111111";
+    const BOB: &str = "0x2222222222222222222222222222222222222222";
+    const CAROL: &str = "0x3333333333333333333333333333333333333333";
+
+    const ALL: TransferKinds = TransferKinds {
+        regular: true,
+        erc20: true,
+        erc721: true,
+        erc1155: true,
+        unindexed_contracts: Vec::new(),
+    };
+    const REGULAR: TransferKinds = TransferKinds {
+        regular: true,
+        erc20: false,
+        erc721: false,
+        erc1155: false,
+        unindexed_contracts: Vec::new(),
+    };
+
+    fn range(from_block: u64, next_block: u64, kinds: TransferKinds) -> CachedRange {
+        CachedRange {
+            from_block,
+            next_block,
+            kinds,
+        }
+    }
+
+    fn transfers(transfers: &[(u64, &str, &str)]) -> Transfers {
+        let mut result = Transfers::new();
+        for (block, from, to) in transfers {
+            result.regular_transfers.push(RegularTransfer {
+                hash: format!("0x{}", block),
+                block_hash: String::new(),
+                block: block.to_string(),
+                timestamp: None,
+                nonce: "0".to_string(),
+                to: to.to_string(),
+                from: from.to_string(),
+                value: "1".to_string(),
+                gas_used: "21000".to_string(),
+                internal: false,
+            });
+        }
+        result
+    }
+
+    fn blocks(transfers: &Transfers) -> Vec<u64> {
+        let mut blocks: Vec<u64> = transfers
+            .regular_transfers
+            .iter()
+            .map(|transfer| transfer.block.parse().unwrap())
+            .collect();
+        blocks.sort();
+        blocks
+    }
+
+    // Cache database of one test, removed with its directory when dropped
+    struct TestDb(PathBuf);
+
+    impl TestDb {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("hypertui-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TestDb(dir.join("transfers.db"))
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            if let Some(dir) = self.0.parent() {
+                let _ = fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    const KITTIES: &str = "0x06012c8cf97bead5deae237070f9587f8e7a266d";
+
+    fn with_contracts(contracts: &[&str]) -> TransferKinds {
+        TransferKinds {
+            unindexed_contracts: contracts.iter().map(|c| c.to_string()).collect(),
+            ..ALL
+        }
+    }
+
+    #[test]
+    fn stores_kinds_as_columns() {
+        assert_eq!(REGULAR.bits(), 1);
+        assert_eq!(ALL.bits(), 15);
+        for bits in 0..16 {
+            assert_eq!(TransferKinds::from_columns(bits, "").bits(), bits);
+        }
+        let kinds = with_contracts(&[KITTIES, CAROL]);
+        assert_eq!(
+            TransferKinds::from_columns(kinds.bits(), &kinds.contracts()),
+            kinds
+        );
+    }
+
+    #[test]
+    fn keeps_unindexed_contracts_of_erc721_queries_only() {
+        let mut query = WalletQuery::new();
+        query.unindexed_contracts = vec![
+            CAROL.to_string(),
+            KITTIES.to_uppercase().replacen("0X", "0x", 1),
+            KITTIES.to_string(),
+        ];
+        query.erc721_transfers = true;
+        // Sorted and lowercase, so the same contracts in any order match
+        assert_eq!(
+            TransferKinds::of(&query).unindexed_contracts,
+            vec![KITTIES.to_string(), CAROL.to_string()]
+        );
+        query.erc721_transfers = false;
+        assert!(TransferKinds::of(&query).unindexed_contracts.is_empty());
+    }
+
+    #[test]
+    fn skips_ranges_missing_unindexed_contracts() {
+        let ranges = [
+            range(0, 100, with_contracts(&[KITTIES])),
+            range(100, 200, ALL),
+        ];
+        assert_eq!(
+            select_ranges(&ranges, 0, &ALL),
+            vec![
+                (range(0, 100, with_contracts(&[KITTIES])), 0),
+                (range(100, 200, ALL), 100)
+            ]
+        );
+        assert_eq!(
+            select_ranges(&ranges, 0, &with_contracts(&[KITTIES])),
+            vec![(range(0, 100, with_contracts(&[KITTIES])), 0)]
+        );
+        assert!(range(0, 100, ALL).is_superseded_by(&range(0, 100, with_contracts(&[KITTIES]))));
+        assert!(!range(0, 100, with_contracts(&[KITTIES])).is_superseded_by(&range(0, 100, ALL)));
+    }
+
+    #[test]
+    fn selects_contiguous_ranges() {
+        let ranges = [
+            range(0, 100, ALL),
+            range(100, 150, ALL),
+            range(200, 300, ALL),
+        ];
+        assert_eq!(
+            select_ranges(&ranges, 50, &ALL),
+            vec![(range(0, 100, ALL), 50), (range(100, 150, ALL), 100)]
+        );
+        // The gap at 150 ends the cover
+        assert_eq!(
+            select_ranges(&ranges, 250, &ALL),
+            vec![(range(200, 300, ALL), 250)]
+        );
+        assert!(select_ranges(&ranges, 150, &ALL).is_empty());
+        assert!(select_ranges(&ranges, 300, &ALL).is_empty());
+    }
+
+    #[test]
+    fn selects_overlapping_ranges_reaching_furthest() {
+        let ranges = [range(0, 100, ALL), range(50, 250, ALL), range(80, 120, ALL)];
+        assert_eq!(
+            select_ranges(&ranges, 0, &ALL),
+            vec![(range(0, 100, ALL), 0), (range(50, 250, ALL), 100)]
+        );
+    }
+
+    #[test]
+    fn skips_ranges_missing_transfer_kinds() {
+        let ranges = [range(0, 100, ALL), range(100, 200, REGULAR)];
+        assert_eq!(
+            select_ranges(&ranges, 0, &REGULAR),
+            vec![(range(0, 100, ALL), 0), (range(100, 200, REGULAR), 100)]
+        );
+        assert_eq!(
+            select_ranges(&ranges, 0, &ALL),
+            vec![(range(0, 100, ALL), 0)]
+        );
+    }
+
+    #[test]
+    fn replaces_contained_ranges_only() {
+        let scan = range(100, 200, REGULAR);
+        assert!(range(120, 180, REGULAR).is_superseded_by(&scan));
+        assert!(range(100, 200, REGULAR).is_superseded_by(&scan));
+        assert!(!range(50, 150, REGULAR).is_superseded_by(&scan));
+        assert!(!range(120, 180, ALL).is_superseded_by(&scan));
+    }
+
+    #[test]
+    fn resumes_after_cached_blocks() {
+        let db = TestDb::new("cache-resume");
+        let scanned = transfers(&[(10, ALICE, BOB), (60, CAROL, ALICE)]);
+        let addresses = [ALICE.to_string()];
+        save_results_in(&db.0, 1, &addresses, 0, 100, ALL, &scanned).unwrap();
+        let scanned = transfers(&[(120, ALICE, CAROL)]);
+        save_results_in(&db.0, 1, &addresses, 100, 150, ALL, &scanned).unwrap();
+
+        let (resume_block, cached) = load_cached_in(&db.0, 1, &addresses, 50, None, ALL);
+        assert_eq!(resume_block, 150);
+        assert_eq!(blocks(&cached), vec![60, 120]);
+
+        // The end block is inclusive
+        let (resume_block, cached) = load_cached_in(&db.0, 1, &addresses, 0, Some(99), ALL);
+        assert_eq!(resume_block, 100);
+        assert_eq!(blocks(&cached), vec![10, 60]);
+
+        // Other chains and later start blocks have no cover
+        assert_eq!(load_cached_in(&db.0, 10, &addresses, 0, None, ALL).0, 0);
+        assert_eq!(load_cached_in(&db.0, 1, &addresses, 150, None, ALL).0, 150);
+    }
+
+    #[test]
+    fn resumes_at_the_earliest_wallet_cache() {
+        let db = TestDb::new("cache-wallets");
+        let scanned = transfers(&[(10, ALICE, BOB), (120, ALICE, CAROL)]);
+        let both = [ALICE.to_string(), BOB.to_string()];
+        save_results_in(&db.0, 1, &both, 0, 100, ALL, &scanned).unwrap();
+        save_results_in(&db.0, 1, &both[..1], 100, 200, ALL, &scanned).unwrap();
+
+        let (resume_block, cached) = load_cached_in(&db.0, 1, &both, 0, None, ALL);
+        assert_eq!(resume_block, 100);
+        // The transfer between both wallets is cached for each, but loaded once
+        assert_eq!(blocks(&cached), vec![10]);
+    }
+
+    #[test]
+    fn merges_and_replaces_saved_ranges() {
+        let db = TestDb::new("cache-merge");
+        let addresses = [ALICE.to_string()];
+        save_results_in(
+            &db.0,
+            1,
+            &addresses,
+            0,
+            100,
+            ALL,
+            &transfers(&[(10, ALICE, BOB)]),
+        )
+        .unwrap();
+        save_results_in(&db.0, 1, &addresses, 100, 120, ALL, &Transfers::new()).unwrap();
+        // A rescan of the same blocks replaces both earlier ranges
+        let scanned = transfers(&[(10, ALICE, BOB), (110, BOB, ALICE)]);
+        save_results_in(&db.0, 1, &addresses, 0, 150, ALL, &scanned).unwrap();
+
+        let connection = open(&db.0).unwrap();
+        assert_eq!(
+            cached_ranges(&connection, 1, ALICE),
+            vec![range(0, 150, ALL)]
+        );
+        let (resume_block, cached) = load_cached_in(&db.0, 1, &addresses, 0, None, ALL);
+        assert_eq!(resume_block, 150);
+        assert_eq!(blocks(&cached), vec![10, 110]);
+    }
+
+    #[test]
+    fn rescans_blocks_after_an_unreadable_range() {
+        let db = TestDb::new("cache-unreadable");
+        let addresses = [ALICE.to_string()];
+        save_results_in(&db.0, 1, &addresses, 0, 100, ALL, &Transfers::new()).unwrap();
+        save_results_in(&db.0, 1, &addresses, 100, 200, ALL, &Transfers::new()).unwrap();
+        open(&db.0)
+            .unwrap()
+            .execute(
+                "UPDATE ranges SET transfers = '{' WHERE from_block = 100",
+                [],
+            )
+            .unwrap();
+
+        assert_eq!(load_cached_in(&db.0, 1, &addresses, 0, None, ALL).0, 100);
+    }
+
+    #[test]
+    fn saves_overlapping_ranges_from_concurrent_queries() {
+        let db = TestDb::new("cache-concurrent");
+        let addresses = [ALICE.to_string()];
+        save_results_in(&db.0, 1, &addresses, 0, 10, ALL, &Transfers::new()).unwrap();
+        let writers: Vec<_> = (1..=4)
+            .map(|i| {
+                let (path, addresses) = (db.0.clone(), addresses.clone());
+                let scanned = transfers(&[(i, ALICE, BOB)]);
+                thread::spawn(move || {
+                    for next_block in 10..30 {
+                        save_results_in(&path, 1, &addresses, 0, next_block + i, ALL, &scanned)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // No save failed or removed a range saved after it, so the longest scan is read
+        let (resume_block, cached) = load_cached_in(&db.0, 1, &addresses, 0, None, ALL);
+        assert_eq!(resume_block, 33);
+        assert_eq!(blocks(&cached), vec![4]);
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufWriter},
+    path::PathBuf,
+    pin::pin,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use futures::StreamExt;
+use tokio::sync::mpsc;
+
+use hypertui::{
+    address_book::AddressBook,
+    cache::{self, TransferKinds},
+    config::{Config, QueryFields},
+    ens::{self, EnsNames},
+    export, hypersync,
+    query::{Chain, WalletQuery},
+    transfers::Transfers,
+};
+
+// Query flags go either before the TUI or after `query`, never both
+#[derive(Parser)]
+#[command(
+    version,
+    about = "Explore wallet transfers with HyperSync",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // Prefills the query builder, and starts the query right away when it is valid
+    #[command(flatten)]
+    pub query: QueryArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run a query without the terminal UI and write the transfers to stdout or a file
+    Query {
+        #[command(flatten)]
+        query: QueryArgs,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+
+        /// File to write to instead of stdout, or the directory for parquet files
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Json,
+    Csv,
+    Parquet,
+}
+
+// Query builder fields, unset ones keep the defaults from the config file
+#[derive(Args)]
+pub struct QueryArgs {
+    /// Wallet address or ENS name, can be repeated or comma separated
+    #[arg(long = "address", short, value_delimiter = ',')]
+    addresses: Vec<String>,
+
+    /// Chain name from the chain registry, e.g. mainnet or optimism
+    #[arg(long)]
+    chain: Option<String>,
+
+    /// Start block, '-N' for N blocks behind the head, or a YYYY-MM-DD date
+    #[arg(long)]
+    from: Option<String>,
+
+    /// End block, 'latest', '-N' or a YYYY-MM-DD date
+    #[arg(long)]
+    to: Option<String>,
+
+    /// Include regular transfers; when no transfer type is given the defaults are used
+    #[arg(long)]
+    regular: bool,
+
+    /// Include ERC20 transfers
+    #[arg(long)]
+    erc20: bool,
+
+    /// Include ERC721 transfers
+    #[arg(long)]
+    erc721: bool,
+
+    /// Include ERC1155 transfers
+    #[arg(long)]
+    erc1155: bool,
+
+    /// ERC721 contract whose Transfer events have no indexed arguments, like CryptoKitties,
+    /// read in full with ERC721 transfers; can be repeated or comma separated
+    #[arg(long = "unindexed-contract", value_delimiter = ',')]
+    unindexed_contracts: Vec<String>,
+}
+
+impl QueryArgs {
+    pub fn has_addresses(&self) -> bool {
+        !self.addresses.is_empty()
+    }
+
+    pub fn apply(&self, query: &mut WalletQuery, chains: &[Chain]) -> Result<(), String> {
+        // Naming any transfer type selects exactly the ones named
+        let any_type = self.regular || self.erc20 || self.erc721 || self.erc1155;
+        let toggle = |flag: bool| any_type.then_some(flag);
+        let fields = QueryFields {
+            addresses: self.has_addresses().then(|| self.addresses.clone()),
+            chain: self.chain.clone(),
+            regular_transfers: toggle(self.regular),
+            erc20_transfers: toggle(self.erc20),
+            erc721_transfers: toggle(self.erc721),
+            erc1155_transfers: toggle(self.erc1155),
+            start_block: self.from.clone(),
+            end_block: self.to.clone(),
+            unindexed_contracts: (!self.unindexed_contracts.is_empty())
+                .then(|| self.unindexed_contracts.clone()),
+        };
+        fields.apply(query, chains)
+    }
+}
+
+// Resolves the ENS names in the query from the cache, then over RPC
+pub async fn resolve_ens_names(query: &mut WalletQuery, ens: &EnsNames) {
+    let mut unresolved = Vec::new();
+    for name in query.ens_names() {
+        match ens.address_of(&name) {
+            Some(address) => {
+                query.ens_addresses.insert(name, address);
+            }
+            None => unresolved.push(name),
+        }
+    }
+    if let (Some(rpc_url), false) = (ens::rpc_url(), unresolved.is_empty()) {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        tokio::spawn(ens::resolve_names(rpc_url, unresolved, sender));
+        while let Some((name, address)) = receiver.recv().await {
+            if let Some(address) = address {
+                query.ens_addresses.insert(name, address);
+            }
+        }
+    }
+}
+
+pub async fn run_query(
+    config: &Config,
+    args: &QueryArgs,
+    format: OutputFormat,
+    output: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let mut query = config.default_query();
+    args.apply(&mut query, config.chains())?;
+    resolve_ens_names(&mut query, &EnsNames::load()?).await;
+    let error = query
+        .address_error()
+        .map(|err| format!("address: {}", err))
+        .or_else(|| {
+            query
+                .start_block_error()
+                .map(|err| format!("from: {}", err))
+        })
+        .or_else(|| query.end_block_error().map(|err| format!("to: {}", err)));
+    if let Some(err) = error {
+        return Err(err.into());
+    }
+    if matches!(format, OutputFormat::Parquet) && output.is_none() {
+        return Err("parquet files are written to a directory, set it with --output".into());
+    }
+    let address_book = AddressBook::load()?;
+
+    let mut batches = pin!(hypersync::fetch_transfers_cached(&query));
+    let mut transfers = Transfers::new();
+    let (mut from_block, mut next_block) = (0, 0);
+    while let Some(batch) = batches.next().await {
+        let batch = batch?;
+        from_block = batch.from_block;
+        next_block = batch.next_block;
+        transfers.extend(batch.transfers);
+    }
+
+    // The same result cache as the TUI, so scheduled runs only scan new blocks
+    let _ = cache::save_results(
+        query.chain.id,
+        &query.address_list(),
+        from_block,
+        next_block,
+        TransferKinds::of(&query),
+        &transfers,
+    );
+
+    match output {
+        Some(path) if matches!(format, OutputFormat::Parquet) => {
+            export::write_parquet(&path, &transfers)?;
+        }
+        Some(path) => {
+            let mut writer = BufWriter::new(File::create(path)?);
+            write(&mut writer, format, &transfers, &address_book)?;
+        }
+        None => {
+            let mut writer = BufWriter::new(io::stdout().lock());
+            write(&mut writer, format, &transfers, &address_book)?;
+        }
+    }
+    Ok(())
+}
+
+fn write(
+    writer: &mut impl io::Write,
+    format: OutputFormat,
+    transfers: &Transfers,
+    address_book: &AddressBook,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Json => export::write_json(writer, transfers, address_book),
+        OutputFormat::Csv => export::write_csv(writer, transfers, address_book),
+        OutputFormat::Parquet => unreachable!("parquet is written to a directory"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_query_flags_before_the_subcommand() {
+        assert!(Cli::try_parse_from(["hypertui", "--chain", "optimism", "query"]).is_err());
+        assert!(Cli::try_parse_from(["hypertui", "query", "--chain", "optimism"]).is_ok());
+        assert!(Cli::try_parse_from(["hypertui", "--chain", "optimism"]).is_ok());
+    }
+}
@@ -0,0 +1,388 @@
+use std::{env, fs, io::ErrorKind, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use toml_edit::{ser::ValueSerializer, DocumentMut, Item};
+
+use crate::query::{validate_address, Chain, WalletQuery};
+
+// Query builder fields, each one optional so a saved query can leave some to the defaults
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct QueryFields {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addresses: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regular_transfers: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub erc20_transfers: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub erc721_transfers: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub erc1155_transfers: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_block: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_block: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unindexed_contracts: Option<Vec<String>>,
+}
+
+impl QueryFields {
+    pub fn of(query: &WalletQuery) -> Self {
+        QueryFields {
+            addresses: Some(
+                query
+                    .addresses
+                    .split(',')
+                    .map(|address| address.trim().to_string())
+                    .filter(|address| !address.is_empty())
+                    .collect(),
+            ),
+            chain: Some(query.chain.name.clone()),
+            regular_transfers: Some(query.regular_transfers),
+            erc20_transfers: Some(query.erc20_transfers),
+            erc721_transfers: Some(query.erc721_transfers),
+            erc1155_transfers: Some(query.erc1155_transfers),
+            start_block: Some(query.start_block.clone()),
+            end_block: Some(query.end_block.clone()),
+            unindexed_contracts: match query.unindexed_contracts.is_empty() {
+                true => None,
+                false => Some(query.unindexed_contracts.clone()),
+            },
+        }
+    }
+
+    // Overwrites the fields that are set, leaving the rest of the query as it is
+    pub fn apply(&self, query: &mut WalletQuery, chains: &[Chain]) -> Result<(), String> {
+        if let Some(addresses) = &self.addresses {
+            query.addresses = addresses.join(", ");
+            query.ens_addresses.clear();
+        }
+        if let Some(chain) = &self.chain {
+            query.chain = chains
+                .iter()
+                .find(|known| known.name.eq_ignore_ascii_case(chain))
+                .cloned()
+                .ok_or_else(|| format!("unknown chain '{}'", chain))?;
+        }
+        if let Some(regular_transfers) = self.regular_transfers {
+            query.regular_transfers = regular_transfers;
+        }
+        if let Some(erc20_transfers) = self.erc20_transfers {
+            query.erc20_transfers = erc20_transfers;
+        }
+        if let Some(erc721_transfers) = self.erc721_transfers {
+            query.erc721_transfers = erc721_transfers;
+        }
+        if let Some(erc1155_transfers) = self.erc1155_transfers {
+            query.erc1155_transfers = erc1155_transfers;
+        }
+        if let Some(start_block) = &self.start_block {
+            query.start_block = start_block.clone();
+        }
+        if let Some(end_block) = &self.end_block {
+            query.end_block = end_block.clone();
+        }
+        if let Some(contracts) = &self.unindexed_contracts {
+            for contract in contracts {
+                validate_address(contract)
+                    .map_err(|err| format!("unindexed contract '{}' {}", contract, err))?;
+            }
+            query.unindexed_contracts = contracts
+                .iter()
+                .map(|contract| contract.to_lowercase())
+                .collect();
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SavedQuery {
+    pub name: String,
+    #[serde(flatten)]
+    pub fields: QueryFields,
+}
+
+#[derive(Default, Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    defaults: QueryFields,
+    #[serde(default)]
+    chains: Vec<Chain>,
+    #[serde(default)]
+    queries: Vec<SavedQuery>,
+}
+
+// Query builder defaults, chains and named saved queries, stored as TOML
+pub struct Config {
+    path: Option<PathBuf>,
+    file: ConfigFile,
+    chains: Vec<Chain>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config {
+            path: None,
+            file: ConfigFile::default(),
+            chains: Chain::builtin(),
+        }
+    }
+
+    // Reads HYPERTUI_CONFIG, or config.toml in the hypertui config directory
+    pub fn load() -> Result<Self, String> {
+        let path = match env::var("HYPERTUI_CONFIG") {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => dirs::config_dir().map(|dir| dir.join("hypertui").join("config.toml")),
+        };
+        let file: ConfigFile = match &path {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(path)
+                    .map_err(|err| format!("could not read config {}: {}", path.display(), err))?;
+                toml::from_str(&content)
+                    .map_err(|err| format!("config {}: {}", path.display(), err))?
+            }
+            _ => ConfigFile::default(),
+        };
+
+        // Configured chains replace built-in chains with the same name
+        let mut chains = Chain::builtin();
+        for chain in &file.chains {
+            match chains
+                .iter_mut()
+                .find(|known| known.name.eq_ignore_ascii_case(&chain.name))
+            {
+                Some(known) => *known = chain.clone(),
+                None => chains.push(chain.clone()),
+            }
+        }
+
+        // Chain names are checked up front so a typo is reported before the TUI starts
+        let mut query = WalletQuery::new();
+        file.defaults
+            .apply(&mut query, &chains)
+            .map_err(|err| format!("config defaults: {}", err))?;
+        for saved in &file.queries {
+            saved
+                .fields
+                .apply(&mut query, &chains)
+                .map_err(|err| format!("config query '{}': {}", saved.name, err))?;
+        }
+
+        Ok(Config { path, file, chains })
+    }
+
+    pub fn chains(&self) -> &[Chain] {
+        &self.chains
+    }
+
+    // The query the builder starts with, on the first chain unless the defaults name one
+    pub fn default_query(&self) -> WalletQuery {
+        let mut query = WalletQuery::new();
+        query.chain = self.chains[0].clone();
+        let _ = self.file.defaults.apply(&mut query, &self.chains);
+        query
+    }
+
+    pub fn saved_queries(&self) -> &[SavedQuery] {
+        &self.file.queries
+    }
+
+    // Builder state of a saved query, with unset fields taken from the defaults
+    pub fn saved_query(&self, i: usize) -> Option<WalletQuery> {
+        let mut query = self.default_query();
+        self.file
+            .queries
+            .get(i)?
+            .fields
+            .apply(&mut query, &self.chains)
+            .ok()?;
+        Some(query)
+    }
+
+    // Saves the query under the name, replacing a saved query with the same name
+    pub fn save_query(&mut self, name: &str, query: &WalletQuery) -> Result<(), String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("a name is required".to_string());
+        }
+        let saved = SavedQuery {
+            name: name.to_string(),
+            fields: QueryFields::of(query),
+        };
+        match self
+            .file
+            .queries
+            .iter_mut()
+            .find(|saved| saved.name == name)
+        {
+            Some(existing) => *existing = saved,
+            None => self.file.queries.push(saved),
+        }
+        self.save()
+    }
+
+    // Only the saved queries are written, the rest of the file keeps its comments and layout
+    fn save(&self) -> Result<(), String> {
+        let path = self.path.as_ref().ok_or("no config directory")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        let mut document = match fs::read_to_string(path) {
+            Ok(content) => content
+                .parse::<DocumentMut>()
+                .map_err(|err| format!("config {}: {}", path.display(), err))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => DocumentMut::new(),
+            Err(err) => {
+                return Err(format!("could not read config {}: {}", path.display(), err));
+            }
+        };
+        let queries = self
+            .file
+            .queries
+            .serialize(ValueSerializer::new())
+            .map_err(|err| err.to_string())?;
+        // A comment above the first saved query stays with it
+        let decor = document
+            .get("queries")
+            .and_then(Item::as_array_of_tables)
+            .and_then(|queries| queries.get(0))
+            .map(|query| query.decor().clone());
+        match Item::Value(queries).into_array_of_tables() {
+            Ok(mut queries) => {
+                if let (Some(decor), Some(first)) = (decor, queries.get_mut(0)) {
+                    *first.decor_mut() = decor;
+                }
+                document["queries"] = Item::ArrayOfTables(queries);
+            }
+            // No saved queries left
+            Err(_) => {
+                document.remove("queries");
+            }
+        }
+        fs::write(path, document.to_string())
+            .map_err(|err| format!("could not write config {}: {}", path.display(), err))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"# Defaults for the query builder
+[defaults]
+chain = "Optimism" # our main chain
+
+# Saved from the query builder
+[[queries]]
+name = "old"
+start_block = "1"
+
+[[chains]]
+name = "Local"
+id = 31337
+url = "http://localhost:8080"
+native_symbol = "ETH"
+"#;
+
+    // Config file of one test, removed when dropped
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str, content: &str) -> Self {
+            let path =
+                env::temp_dir().join(format!("hypertui-{}-{}.toml", name, std::process::id()));
+            fs::write(&path, content).unwrap();
+            TestFile(path)
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn config_at(path: &PathBuf) -> Config {
+        let content = fs::read_to_string(path).unwrap();
+        Config {
+            path: Some(path.clone()),
+            file: toml::from_str(&content).unwrap(),
+            chains: Chain::builtin(),
+        }
+    }
+
+    #[test]
+    fn keeps_comments_when_saving_queries() {
+        let file = TestFile::new("config-comments", CONFIG);
+        let mut config = config_at(&file.0);
+        let mut query = WalletQuery::new();
+        query.addresses = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".to_string();
+        config.save_query("new", &query).unwrap();
+
+        let content = fs::read_to_string(&file.0).unwrap();
+        assert!(content.starts_with("# Defaults for the query builder\n[defaults]\n"));
+        assert!(content.contains("chain = \"Optimism\" # our main chain\n"));
+        assert!(content.contains("# Saved from the query builder\n[[queries]]\nname = \"old\""));
+        assert!(content.contains("[[chains]]\nname = \"Local\"\n"));
+
+        let saved = config_at(&file.0);
+        let names: Vec<&str> = saved
+            .saved_queries()
+            .iter()
+            .map(|saved| saved.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["old", "new"]);
+        assert_eq!(
+            saved.saved_queries()[1].fields.addresses,
+            Some(vec![query.addresses])
+        );
+    }
+
+    #[test]
+    fn creates_missing_config_file() {
+        let file = TestFile::new("config-missing", "");
+        fs::remove_file(&file.0).unwrap();
+        let mut config = Config::new();
+        config.path = Some(file.0.clone());
+        config.save_query("first", &WalletQuery::new()).unwrap();
+
+        let saved = config_at(&file.0);
+        assert_eq!(saved.saved_queries()[0].name, "first");
+        assert_eq!(
+            saved.saved_queries()[0].fields.start_block.as_deref(),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn applies_unindexed_contracts() {
+        let chains = Chain::builtin();
+        let mut query = WalletQuery::new();
+        let fields = QueryFields {
+            unindexed_contracts: Some(vec![
+                "0x06012c8cf97BEaD5deAe237070F9587f8E7A266d".to_string()
+            ]),
+            ..QueryFields::default()
+        };
+        fields.apply(&mut query, &chains).unwrap();
+        assert_eq!(
+            query.unindexed_contracts,
+            vec!["0x06012c8cf97bead5deae237070f9587f8e7a266d"]
+        );
+
+        let fields = QueryFields {
+            unindexed_contracts: Some(vec!["0x06012c".to_string()]),
+            ..QueryFields::default()
+        };
+        assert!(fields.apply(&mut query, &chains).is_err());
+    }
+}
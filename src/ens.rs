@@ -0,0 +1,171 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use ethers::{
+    providers::{Http, Middleware, Provider},
+    types::Address,
+};
+use tokio::sync::mpsc::UnboundedSender;
+
+// Primary ENS names by lowercase address, kept on disk so later sessions work offline
+pub struct EnsNames {
+    names: HashMap<String, String>,
+    pending: HashSet<String>,
+    failed: HashSet<String>,
+}
+
+impl EnsNames {
+    pub fn new() -> Self {
+        EnsNames {
+            names: HashMap::new(),
+            pending: HashSet::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    // Loads the disk cache, then the snapshot from HYPERTUI_ENS_SNAPSHOT which
+    // takes precedence over cached lookups
+    pub fn load() -> Result<Self, String> {
+        let mut ens = EnsNames::new();
+        if let Some(path) = cache_path() {
+            if let Ok(content) = fs::read_to_string(path) {
+                if let Ok(names) = serde_json::from_str(&content) {
+                    ens.names = names;
+                }
+            }
+        }
+        if let Ok(path) = env::var("HYPERTUI_ENS_SNAPSHOT") {
+            ens.names.extend(read_snapshot(Path::new(&path))?);
+        }
+        Ok(ens)
+    }
+
+    pub fn name(&self, address: &str) -> Option<&str> {
+        self.names
+            .get(&address.to_lowercase())
+            .map(|name| name.as_str())
+    }
+
+    // Forward lookup from the cached reverse records, used before asking the RPC
+    pub fn address_of(&self, name: &str) -> Option<String> {
+        self.names
+            .iter()
+            .find(|(_, cached)| cached.eq_ignore_ascii_case(name))
+            .map(|(address, _)| address.clone())
+    }
+
+    // Addresses that are neither cached nor already looked up, marked as pending
+    pub fn unresolved<'a>(&mut self, addresses: impl Iterator<Item = &'a String>) -> Vec<String> {
+        let mut unresolved = Vec::new();
+        for address in addresses {
+            let address = address.to_lowercase();
+            if !self.names.contains_key(&address)
+                && !self.failed.contains(&address)
+                && self.pending.insert(address.clone())
+            {
+                unresolved.push(address);
+            }
+        }
+        unresolved
+    }
+
+    pub fn resolve(&mut self, address: String, name: Option<String>) {
+        self.pending.remove(&address);
+        match name {
+            Some(name) => {
+                self.names.insert(address, name);
+            }
+            // Most addresses have no primary name, they are looked up again next session
+            None => {
+                self.failed.insert(address);
+            }
+        }
+    }
+
+    pub fn is_resolving(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn save_cache(&self) -> Result<(), String> {
+        let path = cache_path().ok_or("no cache directory")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        let content = serde_json::to_string(&self.names).map_err(|err| err.to_string())?;
+        fs::write(path, content).map_err(|err| err.to_string())
+    }
+}
+
+impl Default for EnsNames {
+    fn default() -> Self {
+        EnsNames::new()
+    }
+}
+
+// ENS lives on mainnet, whichever chain is queried
+pub fn rpc_url() -> Option<String> {
+    env::var("HYPERTUI_MAINNET_RPC_URL").ok()
+}
+
+pub fn is_ens_name(entry: &str) -> bool {
+    !entry.starts_with("0x") && entry.contains('.')
+}
+
+fn cache_path() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("hypertui").join("ens.json"))
+}
+
+// Snapshots are a JSON object mapping addresses to names, the same format as the cache
+fn read_snapshot(path: &Path) -> Result<HashMap<String, String>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("could not read ENS snapshot {}: {}", path.display(), err))?;
+    let names: HashMap<String, String> = serde_json::from_str(&content)
+        .map_err(|err| format!("ENS snapshot {}: {}", path.display(), err))?;
+    Ok(names
+        .into_iter()
+        .map(|(address, name)| (address.to_lowercase(), name))
+        .collect())
+}
+
+// Reverse lookups, verified against the forward record by ethers
+pub async fn lookup_addresses(
+    rpc_url: String,
+    addresses: Vec<String>,
+    sender: UnboundedSender<(String, Option<String>)>,
+) {
+    let provider = Provider::<Http>::try_from(rpc_url.as_str()).ok();
+    for address in addresses {
+        let name = match (&provider, address.parse::<Address>()) {
+            (Some(provider), Ok(parsed)) => provider.lookup_address(parsed).await.ok(),
+            _ => None,
+        };
+        if sender.send((address, name)).is_err() {
+            return;
+        }
+    }
+}
+
+// Forward lookups of names typed into the query builder
+pub async fn resolve_names(
+    rpc_url: String,
+    names: Vec<String>,
+    sender: UnboundedSender<(String, Option<String>)>,
+) {
+    let provider = Provider::<Http>::try_from(rpc_url.as_str()).ok();
+    for name in names {
+        let address = match &provider {
+            Some(provider) => provider
+                .resolve_name(&name)
+                .await
+                .ok()
+                .map(|address| format!("{:?}", address)),
+            None => None,
+        };
+        if sender.send((name, address)).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,856 @@
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use arrow::{
+    array::{
+        ArrayRef, BinaryArray, BooleanArray, FixedSizeBinaryArray, FixedSizeBinaryBuilder,
+        ListBuilder, StringArray, TimestampSecondArray, UInt64Array,
+    },
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use chrono::DateTime;
+use ethers::{
+    types::{Address, Bytes, H256},
+    utils::parse_ether,
+};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    errors::ParquetError,
+    file::properties::WriterProperties,
+};
+use serde_json::Value;
+
+use crate::{address_book::AddressBook, transfers::Transfers};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] =
+        [ExportFormat::Json, ExportFormat::Csv, ExportFormat::Parquet];
+
+    pub fn name(&self) -> &str {
+        match self {
+            ExportFormat::Json => "JSON",
+            ExportFormat::Csv => "CSV",
+            ExportFormat::Parquet => "Parquet",
+        }
+    }
+
+    // Parquet exports are a directory, named without an extension
+    pub fn file_name(&self, stem: &str) -> String {
+        match self {
+            ExportFormat::Json => format!("{}.json", stem),
+            ExportFormat::Csv => format!("{}.csv", stem),
+            ExportFormat::Parquet => stem.to_string(),
+        }
+    }
+
+    // The path without the extension file_name adds, keeping other dots the user typed
+    pub fn strip_extension(path: &str) -> &str {
+        [".json", ".csv"]
+            .into_iter()
+            .find_map(|extension| path.strip_suffix(extension))
+            .unwrap_or(path)
+    }
+
+    pub fn next(&self) -> Self {
+        let i = ExportFormat::ALL.iter().position(|format| format == self);
+        ExportFormat::ALL[i.map_or(0, |i| (i + 1) % ExportFormat::ALL.len())]
+    }
+
+    pub fn previous(&self) -> Self {
+        let i = ExportFormat::ALL.iter().position(|format| format == self);
+        let len = ExportFormat::ALL.len();
+        ExportFormat::ALL[i.map_or(0, |i| (i + len - 1) % len)]
+    }
+}
+
+// Writes the export to the path, creating the directories it is in
+pub fn write_file(
+    path: &Path,
+    format: ExportFormat,
+    transfers: &Transfers,
+    address_book: &AddressBook,
+) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut writer = match format {
+        ExportFormat::Parquet => return write_parquet(path, transfers),
+        _ => BufWriter::new(File::create(path)?),
+    };
+    match format {
+        ExportFormat::Json => write_json(&mut writer, transfers, address_book),
+        _ => write_csv(&mut writer, transfers, address_book),
+    }
+}
+
+// Transfers as JSON, with labels from the address book added next to the addresses they name
+pub fn transfers_json(transfers: &Transfers, address_book: &AddressBook) -> io::Result<Value> {
+    let mut transfers = serde_json::to_value(transfers)?;
+    if let Value::Object(sections) = &mut transfers {
+        for section in sections.values_mut() {
+            if let Value::Array(section) = section {
+                for transfer in section {
+                    if let Value::Object(fields) = transfer {
+                        for field in ["from", "to", "contract"] {
+                            let label = fields
+                                .get(field)
+                                .and_then(|address| address.as_str())
+                                .and_then(|address| address_book.label(address))
+                                .map(|label| label.to_string());
+                            if let Some(label) = label {
+                                fields.insert(format!("{}_label", field), Value::String(label));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(transfers)
+}
+
+pub fn write_json(
+    writer: &mut impl Write,
+    transfers: &Transfers,
+    address_book: &AddressBook,
+) -> io::Result<()> {
+    serde_json::to_writer(&mut *writer, &transfers_json(transfers, address_book)?)?;
+    writer.flush()
+}
+
+const CSV_HEADER: &str =
+    "kind,timestamp,block,hash,from,to,contract,amount,token_id,from_label,to_label,contract_label";
+
+// All transfer types in one table with a kind column. Timestamps are UTC, regular transfer
+// amounts are in ether and token amounts are raw, without the token decimals applied
+pub fn write_csv(
+    writer: &mut impl Write,
+    transfers: &Transfers,
+    address_book: &AddressBook,
+) -> io::Result<()> {
+    writeln!(writer, "{}", CSV_HEADER)?;
+    let mut row = |kind: &str, timestamp: Option<u64>, fields: [&str; 7]| -> io::Result<()> {
+        let [block, hash, from, to, contract, amount, token_id] = fields;
+        let timestamp = timestamp
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp as i64, 0))
+            .map(|datetime| datetime.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let label = |address: &str| address_book.label(address).unwrap_or_default();
+        let fields = [
+            kind,
+            &timestamp,
+            block,
+            hash,
+            from,
+            to,
+            contract,
+            amount,
+            token_id,
+            label(from),
+            label(to),
+            label(contract),
+        ];
+        let fields: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+        writeln!(writer, "{}", fields.join(","))
+    };
+
+    for transfer in &transfers.regular_transfers {
+        row(
+            "regular",
+            transfer.timestamp,
+            [
+                &transfer.block,
+                &transfer.hash,
+                &transfer.from,
+                &transfer.to,
+                "",
+                &transfer.value,
+                "",
+            ],
+        )?;
+    }
+    for transfer in &transfers.erc20_transfers {
+        row(
+            "erc20",
+            transfer.timestamp,
+            [
+                &transfer.block,
+                &transfer.hash,
+                &transfer.from,
+                &transfer.to,
+                &transfer.contract,
+                &transfer.amount,
+                "",
+            ],
+        )?;
+    }
+    for transfer in &transfers.erc721_transfers {
+        row(
+            "erc721",
+            transfer.timestamp,
+            [
+                &transfer.block,
+                &transfer.hash,
+                &transfer.from,
+                &transfer.to,
+                &transfer.contract,
+                "",
+                &transfer.token_id,
+            ],
+        )?;
+    }
+    for transfer in &transfers.erc1155_transfers {
+        row(
+            "erc1155",
+            transfer.timestamp,
+            [
+                &transfer.block,
+                &transfer.hash,
+                &transfer.from,
+                &transfer.to,
+                &transfer.contract,
+                &transfer.amount,
+                &transfer.token_id,
+            ],
+        )?;
+    }
+    for transfer in &transfers.unknown_transfers {
+        row(
+            "unknown",
+            transfer.timestamp,
+            [
+                &transfer.block,
+                &transfer.hash,
+                "",
+                "",
+                &transfer.contract,
+                "",
+                "",
+            ],
+        )?;
+    }
+    writer.flush()
+}
+
+// Quotes fields containing commas, quotes or line breaks, which labels may have
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+// Typed Parquet files, one per transfer type, in the directory. Addresses and hashes are
+// fixed size binary, token amounts and ids decimal strings and ETH values in wei
+pub fn write_parquet(dir: &Path, transfers: &Transfers) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    write_parquet_files(dir, transfers).map_err(io::Error::from)
+}
+
+fn write_parquet_files(dir: &Path, transfers: &Transfers) -> Result<(), ParquetError> {
+    let regular = &transfers.regular_transfers;
+    write_parquet_file(
+        &dir.join("regular.parquet"),
+        vec![
+            (
+                "hash",
+                hashes(regular.iter().map(|t| t.hash.as_str()))?,
+                false,
+            ),
+            (
+                "block_hash",
+                hashes(regular.iter().map(|t| t.block_hash.as_str()))?,
+                false,
+            ),
+            (
+                "block",
+                blocks(regular.iter().map(|t| t.block.as_str())),
+                false,
+            ),
+            (
+                "timestamp",
+                timestamps(regular.iter().map(|t| t.timestamp)),
+                true,
+            ),
+            ("nonce", wei(regular.iter().map(|t| t.nonce.as_str())), true),
+            (
+                "from",
+                addresses(regular.iter().map(|t| t.from.as_str()))?,
+                false,
+            ),
+            // Contract creations have no recipient
+            (
+                "to",
+                addresses(regular.iter().map(|t| t.to.as_str()))?,
+                true,
+            ),
+            (
+                "value_wei",
+                wei_strings(regular.iter().map(|t| t.value.as_str())),
+                false,
+            ),
+            (
+                "gas_used",
+                wei(regular.iter().map(|t| t.gas_used.as_str())),
+                true,
+            ),
+            ("internal", flags(regular.iter().map(|t| t.internal)), false),
+        ],
+    )?;
+
+    let erc20 = &transfers.erc20_transfers;
+    write_parquet_file(
+        &dir.join("erc20.parquet"),
+        vec![
+            (
+                "hash",
+                hashes(erc20.iter().map(|t| t.hash.as_str()))?,
+                false,
+            ),
+            (
+                "block",
+                blocks(erc20.iter().map(|t| t.block.as_str())),
+                false,
+            ),
+            (
+                "timestamp",
+                timestamps(erc20.iter().map(|t| t.timestamp)),
+                true,
+            ),
+            (
+                "contract",
+                addresses(erc20.iter().map(|t| t.contract.as_str()))?,
+                false,
+            ),
+            (
+                "from",
+                addresses(erc20.iter().map(|t| t.from.as_str()))?,
+                false,
+            ),
+            ("to", addresses(erc20.iter().map(|t| t.to.as_str()))?, false),
+            (
+                "amount",
+                strings(erc20.iter().map(|t| t.amount.as_str())),
+                false,
+            ),
+            ("internal", flags(erc20.iter().map(|t| t.internal)), false),
+        ],
+    )?;
+
+    let erc721 = &transfers.erc721_transfers;
+    write_parquet_file(
+        &dir.join("erc721.parquet"),
+        vec![
+            (
+                "hash",
+                hashes(erc721.iter().map(|t| t.hash.as_str()))?,
+                false,
+            ),
+            (
+                "block",
+                blocks(erc721.iter().map(|t| t.block.as_str())),
+                false,
+            ),
+            (
+                "timestamp",
+                timestamps(erc721.iter().map(|t| t.timestamp)),
+                true,
+            ),
+            (
+                "contract",
+                addresses(erc721.iter().map(|t| t.contract.as_str()))?,
+                false,
+            ),
+            (
+                "from",
+                addresses(erc721.iter().map(|t| t.from.as_str()))?,
+                false,
+            ),
+            (
+                "to",
+                addresses(erc721.iter().map(|t| t.to.as_str()))?,
+                false,
+            ),
+            (
+                "token_id",
+                strings(erc721.iter().map(|t| t.token_id.as_str())),
+                false,
+            ),
+            ("internal", flags(erc721.iter().map(|t| t.internal)), false),
+        ],
+    )?;
+
+    let erc1155 = &transfers.erc1155_transfers;
+    write_parquet_file(
+        &dir.join("erc1155.parquet"),
+        vec![
+            (
+                "hash",
+                hashes(erc1155.iter().map(|t| t.hash.as_str()))?,
+                false,
+            ),
+            (
+                "block",
+                blocks(erc1155.iter().map(|t| t.block.as_str())),
+                false,
+            ),
+            (
+                "timestamp",
+                timestamps(erc1155.iter().map(|t| t.timestamp)),
+                true,
+            ),
+            (
+                "contract",
+                addresses(erc1155.iter().map(|t| t.contract.as_str()))?,
+                false,
+            ),
+            (
+                "operator",
+                addresses(erc1155.iter().map(|t| t.operator.as_str()))?,
+                false,
+            ),
+            (
+                "from",
+                addresses(erc1155.iter().map(|t| t.from.as_str()))?,
+                false,
+            ),
+            (
+                "to",
+                addresses(erc1155.iter().map(|t| t.to.as_str()))?,
+                false,
+            ),
+            (
+                "token_id",
+                strings(erc1155.iter().map(|t| t.token_id.as_str())),
+                false,
+            ),
+            (
+                "amount",
+                strings(erc1155.iter().map(|t| t.amount.as_str())),
+                false,
+            ),
+            ("internal", flags(erc1155.iter().map(|t| t.internal)), false),
+        ],
+    )?;
+
+    let unknown = &transfers.unknown_transfers;
+    let mut topics = ListBuilder::new(FixedSizeBinaryBuilder::new(32));
+    for transfer in unknown {
+        for topic in &transfer.topics {
+            match topic.parse::<H256>() {
+                Ok(topic) => topics.values().append_value(topic.as_bytes())?,
+                Err(_) => topics.values().append_null(),
+            }
+        }
+        topics.append(true);
+    }
+    let data = unknown
+        .iter()
+        .map(|t| t.data.parse::<Bytes>().ok())
+        .collect::<Vec<_>>();
+    write_parquet_file(
+        &dir.join("unknown.parquet"),
+        vec![
+            (
+                "hash",
+                hashes(unknown.iter().map(|t| t.hash.as_str()))?,
+                false,
+            ),
+            (
+                "block",
+                blocks(unknown.iter().map(|t| t.block.as_str())),
+                false,
+            ),
+            (
+                "timestamp",
+                timestamps(unknown.iter().map(|t| t.timestamp)),
+                true,
+            ),
+            (
+                "contract",
+                addresses(unknown.iter().map(|t| t.contract.as_str()))?,
+                false,
+            ),
+            ("topics", Arc::new(topics.finish()), false),
+            (
+                "data",
+                Arc::new(BinaryArray::from_iter(
+                    data.iter()
+                        .map(|data| data.as_ref().map(|data| data.as_ref())),
+                )),
+                true,
+            ),
+        ],
+    )
+}
+
+fn write_parquet_file(
+    path: &Path,
+    columns: Vec<(&str, ArrayRef, bool)>,
+) -> Result<(), ParquetError> {
+    let batch = RecordBatch::try_from_iter_with_nullable(columns)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
+
+fn addresses<'a>(values: impl Iterator<Item = &'a str>) -> Result<ArrayRef, ArrowError> {
+    let values = values.map(|value| value.parse::<Address>().ok().map(|address| address.0));
+    let array = FixedSizeBinaryArray::try_from_sparse_iter_with_size(values, 20)?;
+    Ok(Arc::new(array))
+}
+
+fn hashes<'a>(values: impl Iterator<Item = &'a str>) -> Result<ArrayRef, ArrowError> {
+    let values = values.map(|value| value.parse::<H256>().ok().map(|hash| hash.0));
+    let array = FixedSizeBinaryArray::try_from_sparse_iter_with_size(values, 32)?;
+    Ok(Arc::new(array))
+}
+
+fn blocks<'a>(values: impl Iterator<Item = &'a str>) -> ArrayRef {
+    Arc::new(UInt64Array::from_iter(
+        values.map(|value| value.parse::<u64>().ok()),
+    ))
+}
+
+fn timestamps(values: impl Iterator<Item = Option<u64>>) -> ArrayRef {
+    let values = values.map(|value| value.map(|value| value as i64));
+    Arc::new(TimestampSecondArray::from_iter(values).with_timezone("UTC"))
+}
+
+// Nonces and gas used are kept formatted as ether, this turns them back into integers
+fn wei<'a>(values: impl Iterator<Item = &'a str>) -> ArrayRef {
+    Arc::new(UInt64Array::from_iter(values.map(|value| {
+        parse_ether(value)
+            .ok()
+            .and_then(|value| u64::try_from(value).ok())
+    })))
+}
+
+fn wei_strings<'a>(values: impl Iterator<Item = &'a str>) -> ArrayRef {
+    Arc::new(StringArray::from_iter(values.map(|value| {
+        parse_ether(value).ok().map(|value| value.to_string())
+    })))
+}
+
+fn strings<'a>(values: impl Iterator<Item = &'a str>) -> ArrayRef {
+    Arc::new(StringArray::from_iter_values(values))
+}
+
+fn flags(values: impl Iterator<Item = bool>) -> ArrayRef {
+    Arc::new(BooleanArray::from_iter(values.map(Some)))
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::{
+        array::{Array, AsArray},
+        datatypes::{DataType, TimeUnit, UInt64Type},
+    };
+    use ethers::utils::format_ether;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    use super::*;
+    use crate::transfers::{Erc20Transfer, RegularTransfer, UnknownTransfer};
+
+    const ALICE: &str = "0x1111111111111111111111111111111111111111";
+    const BOB: &str = "0x2222222222222222222222222222222222222222";
+    const USDC: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+    const HASH: &str = "0x88df016429689c079f3b2f6ad39fa052532c56795b733da78a91ebe6a713944b";
+
+    fn erc20_transfers() -> Transfers {
+        let mut transfers = Transfers::new();
+        transfers.erc20_transfers.push(Erc20Transfer {
+            hash: HASH.to_string(),
+            block: "19000000".to_string(),
+            timestamp: Some(1705173443),
+            contract: USDC.to_string(),
+            to: BOB.to_string(),
+            from: ALICE.to_string(),
+            amount: "1500000".to_string(),
+            internal: false,
+        });
+        transfers
+    }
+
+    fn csv(transfers: &Transfers, address_book: &AddressBook) -> String {
+        let mut output = Vec::new();
+        write_csv(&mut output, transfers, address_book).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn strips_only_export_extensions() {
+        assert_eq!(
+            ExportFormat::strip_extension("outputs/wallet.json"),
+            "outputs/wallet"
+        );
+        assert_eq!(
+            ExportFormat::strip_extension("outputs/wallet.csv"),
+            "outputs/wallet"
+        );
+        assert_eq!(
+            ExportFormat::strip_extension("outputs/v1.2"),
+            "outputs/v1.2"
+        );
+        assert_eq!(ExportFormat::strip_extension("my.wallet.json"), "my.wallet");
+        assert_eq!(
+            ExportFormat::strip_extension("wallet.parquet"),
+            "wallet.parquet"
+        );
+        for format in ExportFormat::ALL {
+            let path = format.file_name("outputs/v1.2");
+            assert_eq!(ExportFormat::strip_extension(&path), "outputs/v1.2");
+        }
+    }
+
+    #[test]
+    fn leaves_plain_csv_fields_unquoted() {
+        assert!(matches!(csv_field(ALICE), Cow::Borrowed(ALICE)));
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("Alice's wallet"), "Alice's wallet");
+    }
+
+    #[test]
+    fn quotes_csv_fields_with_separators() {
+        assert_eq!(csv_field("Alice, Bob"), "\"Alice, Bob\"");
+        assert_eq!(
+            csv_field("the \"hot\" wallet"),
+            "\"the \"\"hot\"\" wallet\""
+        );
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_field("carriage\rreturn"), "\"carriage\rreturn\"");
+    }
+
+    #[test]
+    fn writes_csv_rows_in_header_order() {
+        let output = csv(&erc20_transfers(), &AddressBook::new());
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            format!(
+                "erc20,2024-01-13 19:17:23,19000000,{},{},{},{},1500000,,,,",
+                HASH, ALICE, BOB, USDC
+            )
+        );
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn quotes_labels_in_csv_rows() {
+        let mut address_book = AddressBook::new();
+        // Without a labels file the labels are only kept in memory
+        address_book
+            .set_label(ALICE, "Alice, \"main\"")
+            .unwrap_err();
+        address_book.set_label(USDC, "USD Coin").unwrap_err();
+
+        let output = csv(&erc20_transfers(), &address_book);
+        let row = output.lines().nth(1).unwrap();
+        assert!(row.ends_with(",\"Alice, \"\"main\"\"\",,USD Coin"));
+    }
+
+    #[test]
+    fn writes_csv_header_without_transfers() {
+        let output = csv(&Transfers::new(), &AddressBook::new());
+        assert_eq!(output, format!("{}\n", CSV_HEADER));
+    }
+
+    // Parquet directory of one test, removed when dropped
+    struct TestDir(std::path::PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("hypertui-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            TestDir(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn read_parquet(path: &Path) -> RecordBatch {
+        let builder = ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap()).unwrap();
+        let schema = builder.schema().clone();
+        let batches: Vec<RecordBatch> = builder
+            .build()
+            .unwrap()
+            .map(|batch| batch.unwrap())
+            .collect();
+        arrow::compute::concat_batches(&schema, &batches).unwrap()
+    }
+
+    fn columns(batch: &RecordBatch) -> Vec<(String, DataType, bool)> {
+        batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| {
+                (
+                    field.name().to_string(),
+                    field.data_type().clone(),
+                    field.is_nullable(),
+                )
+            })
+            .collect()
+    }
+
+    fn column(name: &str, data_type: DataType, nullable: bool) -> (String, DataType, bool) {
+        (name.to_string(), data_type, nullable)
+    }
+
+    #[test]
+    fn writes_one_parquet_file_per_transfer_type() {
+        let dir = TestDir::new("parquet-files");
+        write_parquet(&dir.0, &Transfers::new()).unwrap();
+        for name in ["regular", "erc20", "erc721", "erc1155", "unknown"] {
+            let batch = read_parquet(&dir.0.join(format!("{}.parquet", name)));
+            assert_eq!(batch.num_rows(), 0, "{}", name);
+        }
+    }
+
+    #[test]
+    fn writes_typed_regular_transfer_columns() {
+        let dir = TestDir::new("parquet-regular");
+        let mut transfers = Transfers::new();
+        transfers.regular_transfers.push(RegularTransfer {
+            hash: HASH.to_string(),
+            block_hash: HASH.to_string(),
+            block: "19000000".to_string(),
+            timestamp: None,
+            nonce: format_ether(5),
+            // Contract creation
+            to: String::new(),
+            from: ALICE.to_string(),
+            value: format_ether(1_500_000_000_000_000_000u64),
+            gas_used: format_ether(21000),
+            internal: false,
+        });
+        write_parquet(&dir.0, &transfers).unwrap();
+
+        let batch = read_parquet(&dir.0.join("regular.parquet"));
+        let address = DataType::FixedSizeBinary(20);
+        let hash = DataType::FixedSizeBinary(32);
+        let timestamp = DataType::Timestamp(TimeUnit::Second, Some("UTC".into()));
+        assert_eq!(
+            columns(&batch),
+            vec![
+                column("hash", hash.clone(), false),
+                column("block_hash", hash, false),
+                column("block", DataType::UInt64, false),
+                column("timestamp", timestamp, true),
+                column("nonce", DataType::UInt64, true),
+                column("from", address.clone(), false),
+                column("to", address, true),
+                column("value_wei", DataType::Utf8, false),
+                column("gas_used", DataType::UInt64, true),
+                column("internal", DataType::Boolean, false),
+            ]
+        );
+
+        let uint = |name: &str| batch[name].as_primitive::<UInt64Type>().value(0);
+        assert_eq!(uint("block"), 19000000);
+        assert_eq!(uint("nonce"), 5);
+        assert_eq!(uint("gas_used"), 21000);
+        assert!(batch["timestamp"].is_null(0));
+        assert!(batch["to"].is_null(0));
+        assert_eq!(
+            batch["from"].as_fixed_size_binary().value(0),
+            ALICE.parse::<Address>().unwrap().as_bytes()
+        );
+        assert_eq!(
+            batch["value_wei"].as_string::<i32>().value(0),
+            "1500000000000000000"
+        );
+    }
+
+    #[test]
+    fn writes_typed_erc20_transfer_columns() {
+        let dir = TestDir::new("parquet-erc20");
+        write_parquet(&dir.0, &erc20_transfers()).unwrap();
+
+        let batch = read_parquet(&dir.0.join("erc20.parquet"));
+        let address = DataType::FixedSizeBinary(20);
+        let timestamp = DataType::Timestamp(TimeUnit::Second, Some("UTC".into()));
+        assert_eq!(
+            columns(&batch),
+            vec![
+                column("hash", DataType::FixedSizeBinary(32), false),
+                column("block", DataType::UInt64, false),
+                column("timestamp", timestamp, true),
+                column("contract", address.clone(), false),
+                column("from", address.clone(), false),
+                column("to", address, false),
+                column("amount", DataType::Utf8, false),
+                column("internal", DataType::Boolean, false),
+            ]
+        );
+        assert_eq!(
+            batch["timestamp"]
+                .as_primitive::<arrow::datatypes::TimestampSecondType>()
+                .value(0),
+            1705173443
+        );
+        assert_eq!(batch["amount"].as_string::<i32>().value(0), "1500000");
+    }
+
+    #[test]
+    fn rejects_missing_values_in_required_columns() {
+        let dir = TestDir::new("parquet-required");
+        let mut transfers = erc20_transfers();
+        transfers.erc20_transfers[0].from = "not an address".to_string();
+        assert!(write_parquet(&dir.0, &transfers).is_err());
+    }
+
+    #[test]
+    fn writes_unknown_transfer_topics_and_data() {
+        let dir = TestDir::new("parquet-unknown");
+        let mut transfers = Transfers::new();
+        transfers.unknown_transfers.push(UnknownTransfer {
+            hash: HASH.to_string(),
+            block: "19000000".to_string(),
+            timestamp: None,
+            contract: USDC.to_string(),
+            topics: vec![HASH.to_string(), "not a topic".to_string()],
+            data: "not hex".to_string(),
+        });
+        write_parquet(&dir.0, &transfers).unwrap();
+
+        let batch = read_parquet(&dir.0.join("unknown.parquet"));
+        let topics = batch["topics"].as_list::<i32>().value(0);
+        let topics = topics.as_fixed_size_binary();
+        assert_eq!(topics.len(), 2);
+        assert_eq!(topics.value(0), HASH.parse::<H256>().unwrap().as_bytes());
+        assert!(topics.is_null(1));
+        assert!(batch
+            .schema()
+            .field_with_name("data")
+            .unwrap()
+            .is_nullable());
+        assert!(batch["data"].is_null(0));
+    }
+}
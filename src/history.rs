@@ -0,0 +1,116 @@
+use std::{env, fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{query::WalletQuery, transfers::Transfers};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum QueryOutcome {
+    Completed,
+    Cancelled,
+    Failed(String),
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct RowCounts {
+    pub regular: usize,
+    pub erc20: usize,
+    pub erc721: usize,
+    pub erc1155: usize,
+    pub unknown: usize,
+}
+
+impl RowCounts {
+    pub fn of(transfers: &Transfers) -> Self {
+        RowCounts {
+            regular: transfers.regular_transfers.len(),
+            erc20: transfers.erc20_transfers.len(),
+            erc721: transfers.erc721_transfers.len(),
+            erc1155: transfers.erc1155_transfers.len(),
+            unknown: transfers.unknown_transfers.len(),
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.regular + self.erc20 + self.erc721 + self.erc1155 + self.unknown
+    }
+}
+
+// An executed query with the block range it scanned, from_block up to but excluding next_block
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub query: WalletQuery,
+    pub executed_at: i64,
+    pub duration_ms: u64,
+    pub from_block: u64,
+    pub next_block: u64,
+    pub rows: RowCounts,
+    pub outcome: QueryOutcome,
+}
+
+// Executed queries, oldest first, stored as a JSON array
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            path: None,
+            entries: Vec::new(),
+        }
+    }
+
+    // Reads HYPERTUI_HISTORY, or history.json in the hypertui data directory
+    pub fn load() -> Result<Self, String> {
+        let path = match env::var("HYPERTUI_HISTORY") {
+            Ok(path) => Some(PathBuf::from(path)),
+            Err(_) => dirs::data_dir().map(|dir| dir.join("hypertui").join("history.json")),
+        };
+        let entries = match &path {
+            Some(path) if path.exists() => {
+                let content = fs::read_to_string(path)
+                    .map_err(|err| format!("could not read history {}: {}", path.display(), err))?;
+                serde_json::from_str(&content)
+                    .map_err(|err| format!("history {}: {}", path.display(), err))?
+            }
+            _ => Vec::new(),
+        };
+        Ok(History { path, entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Entries newest first, the order they are listed in
+    pub fn recent(&self) -> impl Iterator<Item = &HistoryEntry> {
+        self.entries.iter().rev()
+    }
+
+    pub fn record(&mut self, entry: HistoryEntry) -> Result<(), String> {
+        self.entries.push(entry);
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = self.path.as_ref().ok_or("no data directory")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        let content = serde_json::to_string_pretty(&self.entries).map_err(|err| err.to_string())?;
+        fs::write(path, content)
+            .map_err(|err| format!("could not write history {}: {}", path.display(), err))
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
@@ -1,66 +1,549 @@
-use std::sync::Arc;
+use std::{collections::HashMap, error::Error, fmt, iter, sync::Arc};
 
 use ethers::{core::types::U256, utils::format_ether};
+use futures::stream::{self, Stream};
 use hypersync_client::{
-    format::Hex, net_types::Query, Client, ClientConfig, Decoder, StreamConfig,
+    format::Hex, net_types::Query, simple_types::Log, Client, ClientConfig, Decoder, StreamConfig,
 };
 use serde_json::Value;
+use tokio::{
+    sync::mpsc::{self, UnboundedSender},
+    task::JoinHandle,
+};
+
+use crate::{
+    cache::{self, TransferKinds},
+    query::{parse_block_spec, BlockSpec, Chain, WalletQuery},
+    transfers::{
+        Erc1155Transfer, Erc20Transfer, Erc721Transfer, RegularTransfer, Transfers, UnknownTransfer,
+    },
+};
 
-use crate::app::{App, Chain, Erc20Transfer, Erc721Transfer, RegularTransfer, ScrollbarStates, TableStates, Transfers};
-fn address_to_topic(address: &str) -> String {
-    format!("0x000000000000000000000000{}", &address[2..])
+const TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+const TRANSFER_SINGLE_TOPIC: &str =
+    "0xc3d58168c5ae7397731d063d5bbf3d657854427343f4c083240f7aacaa2d0f62";
+const TRANSFER_BATCH_TOPIC: &str =
+    "0x4a39dc06d4c0dbc64b70af90fd698a233a518aa5d07e595d983b8c0526c8f7fb";
+
+// Transfers found in the blocks up to but excluding next_block. The first batch of a
+// query holds the transfers read from the result cache, if it is used, and may be empty
+pub struct TransferBatch {
+    pub from_block: u64,
+    pub to_block: Option<u64>,
+    pub next_block: u64,
+    pub archive_height: Option<u64>,
+    pub transfers: Transfers,
 }
 
-pub async fn query<'a>(app: &mut App<'a>) {
-    // Remove data from previous query
-    app.transfers = Transfers::new();
-    // Reset tables and scrollbars
-    app.table_states = TableStates::new();
-    app.scrollbar_states = ScrollbarStates::new();
+#[derive(Debug)]
+pub enum QueryError {
+    InvalidInput(String),
+    Transport(String),
+    Decode(String),
+    Server(String),
+}
 
-    let client = Client::new(ClientConfig {
-        url: Some(
-            match &app.query.chain {
-                Chain::Mainnet(link) => link.clone(),
-                Chain::Optimism(link) => link.clone(),
-                Chain::Arbitrum(link) => link.clone(),
-            }
-            .parse()
-            .unwrap(),
-        ),
+impl QueryError {
+    pub fn title(&self) -> &str {
+        match self {
+            QueryError::InvalidInput(_) => "Invalid Input",
+            QueryError::Transport(_) => "Connection Error",
+            QueryError::Decode(_) => "Decode Error",
+            QueryError::Server(_) => "Server Error",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            QueryError::InvalidInput(message)
+            | QueryError::Transport(message)
+            | QueryError::Decode(message)
+            | QueryError::Server(message) => message,
+        }
+    }
+
+    // Classifies a client error by the error that ended the request. reqwest errors keep their
+    // kind, while the client retries failed requests and keeps the error of each attempt as
+    // a message, latest first, starting with the step of the request that failed
+    fn from_client(err: impl AsRef<dyn Error + Send + Sync + 'static>) -> Self {
+        let root: &(dyn Error + 'static) = err.as_ref();
+        let causes = || iter::successors(Some(root), |&cause| cause.source());
+        let message = causes()
+            .map(|cause| cause.to_string())
+            .filter(|message| !message.is_empty())
+            .collect::<Vec<_>>()
+            .join(": ");
+        let kind = causes().find_map(|cause| match cause.downcast_ref::<reqwest::Error>() {
+            Some(err) if err.is_status() => Some(ErrorKind::Server),
+            Some(err) if err.is_decode() => Some(ErrorKind::Decode),
+            Some(_) => Some(ErrorKind::Transport),
+            None => ErrorKind::of_attempt(&cause.to_string()),
+        });
+        match kind.unwrap_or(ErrorKind::Transport) {
+            ErrorKind::Transport => QueryError::Transport(message),
+            ErrorKind::Decode => QueryError::Decode(message),
+            ErrorKind::Server => QueryError::Server(message),
+        }
+    }
+}
+
+enum ErrorKind {
+    Transport,
+    Decode,
+    Server,
+}
+
+impl ErrorKind {
+    // The step a failed attempt of the client stopped at: sending the request and reading the
+    // response, the response status or parsing the response
+    fn of_attempt(message: &str) -> Option<Self> {
+        if message.starts_with("http response status code") {
+            Some(ErrorKind::Server)
+        } else if message.starts_with("parse query response") {
+            Some(ErrorKind::Decode)
+        } else if [
+            "execute http req",
+            "read response body",
+            "read text to see error",
+        ]
+        .iter()
+        .any(|step| message.starts_with(step))
+        {
+            Some(ErrorKind::Transport)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.title(), self.message())
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+fn missing(field: &str) -> QueryError {
+    QueryError::Decode(format!("response is missing field `{}`", field))
+}
+
+fn address_to_topic(address: &str) -> Result<String, QueryError> {
+    match address.strip_prefix("0x") {
+        Some(hex) if hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(format!("0x000000000000000000000000{}", hex))
+        }
+        _ => Err(QueryError::InvalidInput(format!(
+            "'{}' is not a valid wallet address",
+            address
+        ))),
+    }
+}
+
+fn client_for(chain: &Chain) -> Result<Client, QueryError> {
+    let url = &chain.url;
+    Client::new(ClientConfig {
+        url: Some(url.parse().map_err(|err| {
+            QueryError::InvalidInput(format!("'{}' is not a valid url: {}", url, err))
+        })?),
         ..Default::default()
     })
-    .unwrap();
+    .map_err(QueryError::from_client)
+}
+
+async fn resolve_block(client: &Client, block: &str) -> Result<Option<u64>, QueryError> {
+    let spec = parse_block_spec(block)
+        .map_err(|err| QueryError::InvalidInput(format!("'{}' {}", block, err)))?;
+    match spec {
+        BlockSpec::Latest => Ok(None),
+        BlockSpec::Number(number) => Ok(Some(number)),
+        BlockSpec::BehindHead(offset) => {
+            let height = client.get_height().await.map_err(QueryError::from_client)?;
+            Ok(Some(height.saturating_sub(offset)))
+        }
+        BlockSpec::Date(timestamp) => block_at_timestamp(client, timestamp).await.map(Some),
+    }
+}
+
+async fn block_timestamp(client: &Client, block: u64) -> Result<i64, QueryError> {
+    let query: Query = serde_json::from_value(serde_json::json!({
+        "from_block": block,
+        "to_block": block + 1,
+        "include_all_blocks": true,
+        "field_selection": {
+            "block": ["number", "timestamp"]
+        },
+    }))
+    .map_err(|err| QueryError::InvalidInput(err.to_string()))?;
+
+    let res = client.get(&query).await.map_err(QueryError::from_client)?;
+    let timestamp = res
+        .data
+        .blocks
+        .into_iter()
+        .flatten()
+        .next()
+        .and_then(|block| block.timestamp)
+        .ok_or_else(|| missing("timestamp"))?;
+    Ok(U256::from(timestamp.as_ref()).as_u64() as i64)
+}
+
+// Binary search for the first block produced at or after the timestamp
+async fn block_at_timestamp(client: &Client, timestamp: i64) -> Result<u64, QueryError> {
+    let height = client.get_height().await.map_err(QueryError::from_client)?;
+    let mut low = 0;
+    let mut high = height;
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if block_timestamp(client, mid).await? < timestamp {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    Ok(low)
+}
+
+// Resolves date inputs in the query builder so the block numbers can be previewed
+pub async fn resolve_dates(wallet_query: WalletQuery, sender: UnboundedSender<(String, u64)>) {
+    let client = match client_for(&wallet_query.chain) {
+        Ok(client) => client,
+        Err(_) => return,
+    };
+    for block in [wallet_query.start_block, wallet_query.end_block] {
+        if let Ok(BlockSpec::Date(_)) = parse_block_spec(&block) {
+            if let Ok(Some(number)) = resolve_block(&client, &block).await {
+                if sender.send((block, number)).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// Every Transfer event shares topic0, so the standard is told apart by how many
+// arguments are indexed (topics) and what is left in the data
+#[derive(Debug, PartialEq)]
+enum TransferShape {
+    // Transfer(address indexed from, address indexed to, uint256 value)
+    Erc20,
+    // Transfer(address indexed from, address indexed to, uint256 indexed tokenId)
+    Erc721,
+    // Transfer(address from, address to, uint256 tokenId), as emitted by CryptoKitties
+    Erc721Unindexed,
+    Unknown,
+}
+
+fn classify_transfer(num_topics: usize, data_len: Option<usize>) -> TransferShape {
+    match (num_topics, data_len) {
+        (3, Some(32)) => TransferShape::Erc20,
+        (4, Some(0)) => TransferShape::Erc721,
+        (1, Some(96)) => TransferShape::Erc721Unindexed,
+        _ => TransferShape::Unknown,
+    }
+}
+
+// The decoder picks a signature by topic0 and the number of topics
+fn transfer_decoder() -> Result<Decoder, QueryError> {
+    Decoder::from_signatures(&[
+        "Transfer(address indexed from, address indexed to, uint amount)",
+        "Transfer(address indexed from, address indexed to, uint indexed tokenId)",
+        "Transfer(address from, address to, uint tokenId)",
+    ])
+    .map_err(|err| QueryError::Decode(err.to_string()))
+}
+
+enum DecodedTransfer {
+    Erc20(Erc20Transfer),
+    Erc721(Erc721Transfer),
+    Unknown(UnknownTransfer),
+}
+
+impl DecodedTransfer {
+    // Whether one of the wallets sends or receives the transfer, for unknown transfers
+    // whether one of them is an indexed topic
+    fn involves(&self, addresses: &[String], address_topics: &[String]) -> bool {
+        let is_wallet = |address: &str| {
+            addresses
+                .iter()
+                .any(|wallet| wallet.eq_ignore_ascii_case(address))
+        };
+        match self {
+            DecodedTransfer::Erc20(transfer) => {
+                is_wallet(&transfer.from) || is_wallet(&transfer.to)
+            }
+            DecodedTransfer::Erc721(transfer) => {
+                is_wallet(&transfer.from) || is_wallet(&transfer.to)
+            }
+            DecodedTransfer::Unknown(transfer) => transfer.topics.iter().skip(1).any(|topic| {
+                address_topics
+                    .iter()
+                    .any(|address_topic| address_topic.eq_ignore_ascii_case(topic))
+            }),
+        }
+    }
+}
+
+fn decode_transfer(
+    decoder: &Decoder,
+    log: &Log,
+    block_timestamps: &HashMap<u64, u64>,
+) -> Result<DecodedTransfer, QueryError> {
+    let block_number: u64 = log
+        .block_number
+        .ok_or_else(|| missing("block_number"))?
+        .into();
+    let hash = log
+        .transaction_hash
+        .as_ref()
+        .ok_or_else(|| missing("transaction_hash"))?
+        .encode_hex();
+    let contract = log
+        .address
+        .as_ref()
+        .ok_or_else(|| missing("address"))?
+        .encode_hex();
+    let timestamp = block_timestamps.get(&block_number).copied();
+
+    let topics: Vec<String> = log
+        .topics
+        .iter()
+        .flatten()
+        .map(|topic| topic.encode_hex())
+        .collect();
+    let shape = classify_transfer(topics.len(), log.data.as_ref().map(|data| data.len()));
+    let decoded_log = match shape {
+        TransferShape::Unknown => None,
+        _ => decoder.decode_log(log).ok().flatten(),
+    };
+    let decoded_log = match decoded_log {
+        Some(decoded_log) => decoded_log,
+        None => {
+            return Ok(DecodedTransfer::Unknown(UnknownTransfer {
+                hash,
+                block: block_number.to_string(),
+                timestamp,
+                contract,
+                topics,
+                data: log
+                    .data
+                    .as_ref()
+                    .map(|data| data.encode_hex())
+                    .unwrap_or_default(),
+            }))
+        }
+    };
+
+    // from, to and the amount or token id, whichever of them are indexed
+    let args: Vec<_> = decoded_log
+        .indexed
+        .iter()
+        .chain(decoded_log.body.iter())
+        .collect();
+    let from = args
+        .first()
+        .and_then(|value| value.as_address())
+        .ok_or_else(|| missing("from"))?
+        .to_string();
+    let to = args
+        .get(1)
+        .and_then(|value| value.as_address())
+        .ok_or_else(|| missing("to"))?
+        .to_string();
+    let value = args
+        .get(2)
+        .and_then(|value| value.as_uint())
+        .ok_or_else(|| missing("value"))?
+        .0
+        .to_string();
+
+    Ok(match shape {
+        TransferShape::Erc20 => DecodedTransfer::Erc20(Erc20Transfer {
+            hash,
+            block: block_number.to_string(),
+            timestamp,
+            contract,
+            from,
+            to,
+            amount: value,
+            internal: false,
+        }),
+        _ => DecodedTransfer::Erc721(Erc721Transfer {
+            hash,
+            block: block_number.to_string(),
+            timestamp,
+            contract,
+            from,
+            to,
+            token_id: value,
+            internal: false,
+        }),
+    })
+}
+
+// Transfer logs sent or received by the wallets, matched by their indexed topics
+fn log_selections(wallet_query: &WalletQuery, address_topics: &[String]) -> Vec<Value> {
+    let mut selections = vec![
+        serde_json::json!({
+            "topics":[
+                [TRANSFER_TOPIC],
+                [],
+                address_topics,
+                [],
+            ]
+        }),
+        serde_json::json!({
+            "topics":[
+                [TRANSFER_TOPIC],
+                address_topics,
+                [],
+                [],
+            ]
+        }),
+        // ERC1155 events index the operator first, then from and to
+        serde_json::json!({
+            "topics":[
+                [TRANSFER_SINGLE_TOPIC, TRANSFER_BATCH_TOPIC],
+                [],
+                [],
+                address_topics,
+            ]
+        }),
+        serde_json::json!({
+            "topics":[
+                [TRANSFER_SINGLE_TOPIC, TRANSFER_BATCH_TOPIC],
+                [],
+                address_topics,
+                [],
+            ]
+        }),
+    ];
+    // Transfers without indexed arguments can't be filtered by the server, so every Transfer
+    // log of the contracts named for them is read and filtered on its decoded from and to
+    if wallet_query.erc721_transfers && !wallet_query.unindexed_contracts.is_empty() {
+        selections.push(serde_json::json!({
+            "address": wallet_query.unindexed_contracts,
+            "topics": [[TRANSFER_TOPIC]],
+        }));
+    }
+    selections
+}
+
+// Streams the transfers of the query's wallets on the query's chain, batch by batch.
+// The stream ends after the last batch or the first error, and dropping it stops the query.
+// The query runs on a task of its own, so this must be called within a Tokio runtime.
+pub fn fetch_transfers(
+    wallet_query: &WalletQuery,
+) -> impl Stream<Item = Result<TransferBatch, QueryError>> {
+    spawn_query(wallet_query, false)
+}
+
+// Like fetch_transfers, but the blocks the result cache has for the wallets are read from it
+// instead of HyperSync. Saving the results to the cache is left to the caller.
+pub fn fetch_transfers_cached(
+    wallet_query: &WalletQuery,
+) -> impl Stream<Item = Result<TransferBatch, QueryError>> {
+    spawn_query(wallet_query, true)
+}
 
-    let addresses = vec![app.query.address.clone()];
+// Aborts the query task when the stream holding it is dropped, so a cancelled query stops
+// at its next await, also while resolving dates
+struct AbortOnDrop(JoinHandle<()>);
 
-    let address_topic_filter: Vec<String> = addresses.iter().map(|a| address_to_topic(a)).collect();
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn spawn_query(
+    wallet_query: &WalletQuery,
+    use_cache: bool,
+) -> impl Stream<Item = Result<TransferBatch, QueryError>> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let wallet_query = wallet_query.clone();
+    let task = tokio::spawn(async move {
+        if let Err(err) = query(&wallet_query, use_cache, &sender).await {
+            let _ = sender.send(Err(err));
+        }
+    });
+    stream::unfold(
+        (receiver, AbortOnDrop(task)),
+        |(mut receiver, task)| async move {
+            receiver.recv().await.map(|batch| (batch, (receiver, task)))
+        },
+    )
+}
+
+async fn query(
+    wallet_query: &WalletQuery,
+    use_cache: bool,
+    sender: &UnboundedSender<Result<TransferBatch, QueryError>>,
+) -> Result<(), QueryError> {
+    let client = client_for(&wallet_query.chain)?;
+
+    let start_block = resolve_block(&client, &wallet_query.start_block)
+        .await?
+        .ok_or_else(|| {
+            QueryError::InvalidInput(format!(
+                "'{}' is not a valid start block",
+                wallet_query.start_block
+            ))
+        })?;
+    let end_block = resolve_block(&client, &wallet_query.end_block).await?;
+    if let Some(end_block) = end_block {
+        if end_block < start_block {
+            return Err(QueryError::InvalidInput(format!(
+                "To Block {} is before From Block {}",
+                end_block, start_block
+            )));
+        }
+    }
+    let addresses = wallet_query.address_list();
+
+    // Blocks already scanned for these wallets are read from the result cache
+    let (resume_block, mut cached) = match use_cache {
+        true => cache::load_cached(
+            wallet_query.chain.id,
+            &addresses,
+            start_block,
+            end_block,
+            TransferKinds::of(wallet_query),
+        ),
+        false => (start_block, Transfers::new()),
+    };
+    cached.tag_internal(&addresses);
+    let batch = TransferBatch {
+        from_block: start_block,
+        to_block: end_block,
+        next_block: resume_block,
+        archive_height: None,
+        transfers: cached,
+    };
+    // The receiver is closed when the stream is dropped
+    if sender.send(Ok(batch)).is_err() {
+        return Ok(());
+    }
+    if end_block.is_some_and(|end_block| resume_block > end_block) {
+        return Ok(());
+    }
+
+    let address_topic_filter = addresses
+        .iter()
+        .map(|a| address_to_topic(a))
+        .collect::<Result<Vec<String>, QueryError>>()?;
 
     let query: Query = serde_json::from_value(serde_json::json!( {
-        "from_block": app.query.start_block.parse::<u128>().unwrap(),
-        "logs": [
-            {
-                "topics":[
-                    ["0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"],
-                    [],
-                    address_topic_filter,
-                    [],
-                ]
-            },
-            {
-                "topics":[
-                    ["0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"],
-                    address_topic_filter,
-                    [],
-                    [],
-                ]
-            },
-        ],
+        "from_block": resume_block,
+        // HyperSync treats to_block as exclusive while the builder's To Block is inclusive
+        "to_block": end_block.map(|block| block + 1),
+        "logs": log_selections(wallet_query, &address_topic_filter),
         "transactions": [
             {"from": addresses},
             {"to": addresses}
         ],
         "field_selection": {
+            "block": ["number", "timestamp"],
             "log": Value::Array(vec![
                 "transaction_hash".into(),
                 "block_hash".into(),
@@ -85,109 +568,455 @@ pub async fn query<'a>(app: &mut App<'a>) {
             ]
         },
     }))
-    .unwrap();
+    .map_err(|err| QueryError::InvalidInput(err.to_string()))?;
 
     let client = Arc::new(client);
 
-    let mut receiver = client.stream(query, StreamConfig::default()).await.unwrap();
+    let mut receiver = client
+        .stream(query, StreamConfig::default())
+        .await
+        .map_err(QueryError::from_client)?;
 
-    let decoder = Decoder::from_signatures(&[
-        "Transfer(address indexed from, address indexed to, uint amount)",
-    ])
-    .unwrap();
+    let decoder = transfer_decoder()?;
 
-    let erc721_decoder = Decoder::from_signatures(&[
-        "Transfer(address indexed from, address indexed to, uint indexed tokenId)",
+    let erc1155_decoder = Decoder::from_signatures(&[
+        "TransferSingle(address indexed operator, address indexed from, address indexed to, uint256 id, uint256 value)",
+        "TransferBatch(address indexed operator, address indexed from, address indexed to, uint256[] ids, uint256[] values)",
     ])
-    .unwrap();
+    .map_err(|err| QueryError::Decode(err.to_string()))?;
 
     while let Some(res) = receiver.recv().await {
-        let res = res.unwrap();
+        let res = res.map_err(QueryError::from_client)?;
+        let mut transfers = Transfers::new();
+
+        // Blocks in a response cover the same range as its logs and transactions
+        let block_timestamps: HashMap<u64, u64> = res
+            .data
+            .blocks
+            .iter()
+            .flatten()
+            .filter_map(|block| {
+                let timestamp = block.timestamp.as_ref()?;
+                Some((block.number?, U256::from(timestamp.as_ref()).as_u64()))
+            })
+            .collect();
 
         for batch in res.data.logs {
             for log in batch {
-                match &log.data {
-                    Some(_) => {
-                        if let Ok(decoded_log) = decoder.decode_log(&log) {
-                            match &decoded_log {
-                                Some(_) => {
-                                    if app.query.erc20_transfers {
-                                        let decoded_log = decoded_log.unwrap();
-                                        app.transfers.erc20_transfers.push(Erc20Transfer {
-                                            hash: log.transaction_hash.unwrap().encode_hex(),
-                                            block: log.block_number.unwrap().to_string(),
-                                            contract: log.address.unwrap().encode_hex(),
-                                            from: decoded_log.indexed[0]
-                                                .as_address()
-                                                .unwrap()
-                                                .to_string(),
-                                            to: decoded_log.indexed[1]
-                                                .as_address()
-                                                .unwrap()
-                                                .to_string(),
-                                            amount: decoded_log.body[0]
-                                                .as_uint()
-                                                .unwrap()
-                                                .0
-                                                .to_string(),
-                                        });
-                                    }
-                                }
-                                None => {
-                                    if app.query.erc721_transfers {
-                                        if let Ok(decoded_log) = erc721_decoder.decode_log(&log) {
-                                            let decoded_log = decoded_log.unwrap();
-                                            app.transfers.erc721_transfers.push(Erc721Transfer {
-                                                hash: log.transaction_hash.unwrap().encode_hex(),
-                                                block: log.block_number.unwrap().to_string(),
-                                                contract: log.address.unwrap().encode_hex(),
-                                                from: decoded_log.indexed[0]
-                                                    .as_address()
-                                                    .unwrap()
-                                                    .to_string(),
-                                                to: decoded_log.indexed[1]
-                                                    .as_address()
-                                                    .unwrap()
-                                                    .to_string(),
-                                                token_id: decoded_log.indexed[2]
-                                                    .as_uint()
-                                                    .unwrap()
-                                                    .0
-                                                    .to_string(),
-                                            });
-                                        }
-                                    }
-                                }
-                            }
+                let block_number: u64 = log
+                    .block_number
+                    .ok_or_else(|| missing("block_number"))?
+                    .into();
+
+                if let Ok(Some(decoded_log)) = erc1155_decoder.decode_log(&log) {
+                    if !wallet_query.erc1155_transfers {
+                        continue;
+                    }
+                    let hash = log
+                        .transaction_hash
+                        .as_ref()
+                        .ok_or_else(|| missing("transaction_hash"))?
+                        .encode_hex();
+                    let contract = log
+                        .address
+                        .as_ref()
+                        .ok_or_else(|| missing("address"))?
+                        .encode_hex();
+                    let indexed_address = |i: usize, field: &str| {
+                        decoded_log
+                            .indexed
+                            .get(i)
+                            .and_then(|value| value.as_address())
+                            .map(|address| address.to_string())
+                            .ok_or_else(|| missing(field))
+                    };
+                    let operator = indexed_address(0, "operator")?;
+                    let from = indexed_address(1, "from")?;
+                    let to = indexed_address(2, "to")?;
+
+                    // TransferSingle carries one id and value, TransferBatch two arrays
+                    let ids = decoded_log.body.first().ok_or_else(|| missing("id"))?;
+                    let values = decoded_log.body.get(1).ok_or_else(|| missing("value"))?;
+                    let pairs = match (ids.as_array(), values.as_array()) {
+                        // A batch without a value for every id is malformed, and left out
+                        (Some(ids), Some(values)) if ids.len() != values.len() => continue,
+                        (Some(ids), Some(values)) => ids.iter().zip(values.iter()).collect(),
+                        _ => vec![(ids, values)],
+                    };
+                    for (id, value) in pairs {
+                        transfers.erc1155_transfers.push(Erc1155Transfer {
+                            hash: hash.clone(),
+                            block: block_number.to_string(),
+                            timestamp: block_timestamps.get(&block_number).copied(),
+                            contract: contract.clone(),
+                            operator: operator.clone(),
+                            from: from.clone(),
+                            to: to.clone(),
+                            token_id: id.as_uint().ok_or_else(|| missing("id"))?.0.to_string(),
+                            amount: value
+                                .as_uint()
+                                .ok_or_else(|| missing("value"))?
+                                .0
+                                .to_string(),
+                            internal: false,
+                        });
+                    }
+                    continue;
+                }
+
+                let topic0 = log.topics.first().and_then(|topic| topic.as_ref());
+                if topic0.map(|topic| topic.encode_hex()).as_deref() != Some(TRANSFER_TOPIC) {
+                    continue;
+                }
+
+                // A malformed log is left out, so a single contract can't fail the query
+                let transfer = match decode_transfer(&decoder, &log, &block_timestamps) {
+                    Ok(transfer) if transfer.involves(&addresses, &address_topic_filter) => {
+                        transfer
+                    }
+                    _ => continue,
+                };
+                match transfer {
+                    DecodedTransfer::Erc20(transfer) => {
+                        if wallet_query.erc20_transfers {
+                            transfers.erc20_transfers.push(transfer);
+                        }
+                    }
+                    DecodedTransfer::Erc721(transfer) => {
+                        if wallet_query.erc721_transfers {
+                            transfers.erc721_transfers.push(transfer);
+                        }
+                    }
+                    DecodedTransfer::Unknown(transfer) => {
+                        if wallet_query.erc20_transfers || wallet_query.erc721_transfers {
+                            transfers.unknown_transfers.push(transfer);
                         }
                     }
-                    None => {}
                 }
             }
         }
 
         for batch in res.data.transactions {
-            if app.query.regular_transfers {
+            if wallet_query.regular_transfers {
                 for tx in batch {
+                    let block_number: u64 = tx
+                        .block_number
+                        .ok_or_else(|| missing("block_number"))?
+                        .into();
                     let regular_transfer = RegularTransfer {
-                        hash: tx.hash.unwrap().encode_hex(),
-                        block_hash: tx.block_hash.unwrap().encode_hex(),
-                        block: tx.block_number.unwrap().to_string(),
-                        nonce: format_ether(U256::from(tx.nonce.unwrap().as_ref())),
-                        from: tx.from.unwrap().encode_hex(),
-                        to: tx.to.unwrap().encode_hex(),
-                        value: format_ether(U256::from(tx.value.unwrap().as_ref())),
-                        gas_used: format_ether(U256::from(tx.gas_used.unwrap().as_ref())),
+                        hash: tx.hash.ok_or_else(|| missing("hash"))?.encode_hex(),
+                        block_hash: tx
+                            .block_hash
+                            .ok_or_else(|| missing("block_hash"))?
+                            .encode_hex(),
+                        block: block_number.to_string(),
+                        timestamp: block_timestamps.get(&block_number).copied(),
+                        nonce: format_ether(U256::from(
+                            tx.nonce.ok_or_else(|| missing("nonce"))?.as_ref(),
+                        )),
+                        from: tx.from.ok_or_else(|| missing("from"))?.encode_hex(),
+                        // Contract creations have no recipient
+                        to: tx.to.map(|to| to.encode_hex()).unwrap_or_default(),
+                        value: format_ether(U256::from(
+                            tx.value.ok_or_else(|| missing("value"))?.as_ref(),
+                        )),
+                        gas_used: format_ether(U256::from(
+                            tx.gas_used.ok_or_else(|| missing("gas_used"))?.as_ref(),
+                        )),
+                        internal: false,
                     };
-                    let parsed_value = regular_transfer.value.as_str().parse::<f64>().unwrap();
-                    if (regular_transfer.from.to_lowercase() == app.query.address.to_lowercase()
-                        || regular_transfer.to.to_lowercase() == app.query.address.to_lowercase())
+                    let parsed_value = regular_transfer
+                        .value
+                        .parse::<f64>()
+                        .map_err(|err| QueryError::Decode(err.to_string()))?;
+                    if (addresses.contains(&regular_transfer.from.to_lowercase())
+                        || addresses.contains(&regular_transfer.to.to_lowercase()))
                         && parsed_value > 0.0000
                     {
-                        app.transfers.regular_transfers.push(regular_transfer);
+                        transfers.regular_transfers.push(regular_transfer);
                     }
                 }
             }
         }
+
+        transfers.tag_internal(&addresses);
+
+        let batch = TransferBatch {
+            from_block: start_block,
+            to_block: end_block,
+            next_block: res.next_block,
+            archive_height: res.archive_height,
+            transfers,
+        };
+        if sender.send(Ok(batch)).is_err() {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FROM: &str = "0x000000000000000000000000aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+    const TO: &str = "0x000000000000000000000000bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+    const VALUE: &str = "0x00000000000000000000000000000000000000000000000000000000000003e8";
+
+    fn transfer_log(topics: &[&str], data: Option<String>) -> Log {
+        serde_json::from_value(serde_json::json!({
+            "transaction_hash": "0x0101010101010101010101010101010101010101010101010101010101010101",
+            "block_number": "0x10",
+            "address": "0xcccccccccccccccccccccccccccccccccccccccc",
+            "data": data,
+            "topics": topics,
+        }))
+        .unwrap()
+    }
+
+    fn decode(log: &Log) -> DecodedTransfer {
+        decode_transfer(&transfer_decoder().unwrap(), log, &HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn classifies_by_topic_count_and_data_length() {
+        assert_eq!(classify_transfer(3, Some(32)), TransferShape::Erc20);
+        assert_eq!(classify_transfer(4, Some(0)), TransferShape::Erc721);
+        assert_eq!(
+            classify_transfer(1, Some(96)),
+            TransferShape::Erc721Unindexed
+        );
+        assert_eq!(classify_transfer(3, Some(64)), TransferShape::Unknown);
+        assert_eq!(classify_transfer(4, Some(32)), TransferShape::Unknown);
+        assert_eq!(classify_transfer(2, Some(64)), TransferShape::Unknown);
+        assert_eq!(classify_transfer(3, None), TransferShape::Unknown);
+    }
+
+    #[test]
+    fn decodes_erc20_transfer() {
+        let log = transfer_log(&[TRANSFER_TOPIC, FROM, TO], Some(VALUE.to_string()));
+        match decode(&log) {
+            DecodedTransfer::Erc20(transfer) => {
+                assert_eq!(transfer.from.to_lowercase(), format!("0x{}", &FROM[26..]));
+                assert_eq!(transfer.to.to_lowercase(), format!("0x{}", &TO[26..]));
+                assert_eq!(transfer.amount, "1000");
+                assert_eq!(transfer.block, "16");
+            }
+            _ => panic!("expected an ERC20 transfer"),
+        }
+    }
+
+    #[test]
+    fn decodes_erc721_transfer_with_indexed_token_id() {
+        let log = transfer_log(&[TRANSFER_TOPIC, FROM, TO, VALUE], Some("0x".to_string()));
+        match decode(&log) {
+            DecodedTransfer::Erc721(transfer) => {
+                assert_eq!(transfer.from.to_lowercase(), format!("0x{}", &FROM[26..]));
+                assert_eq!(transfer.to.to_lowercase(), format!("0x{}", &TO[26..]));
+                assert_eq!(transfer.token_id, "1000");
+            }
+            _ => panic!("expected an ERC721 transfer"),
+        }
+    }
+
+    #[test]
+    fn decodes_erc721_transfer_without_indexed_arguments() {
+        let data = format!("0x{}{}{}", &FROM[2..], &TO[2..], &VALUE[2..]);
+        let log = transfer_log(&[TRANSFER_TOPIC], Some(data));
+        match decode(&log) {
+            DecodedTransfer::Erc721(transfer) => {
+                assert_eq!(transfer.from.to_lowercase(), format!("0x{}", &FROM[26..]));
+                assert_eq!(transfer.to.to_lowercase(), format!("0x{}", &TO[26..]));
+                assert_eq!(transfer.token_id, "1000");
+            }
+            _ => panic!("expected an ERC721 transfer"),
+        }
+    }
+
+    #[test]
+    fn keeps_ambiguous_transfer_as_unknown() {
+        let data = format!("0x{}{}", &VALUE[2..], &VALUE[2..]);
+        let log = transfer_log(&[TRANSFER_TOPIC, FROM, TO], Some(data.clone()));
+        match decode(&log) {
+            DecodedTransfer::Unknown(transfer) => {
+                assert_eq!(transfer.topics, vec![TRANSFER_TOPIC, FROM, TO]);
+                assert_eq!(transfer.data, data);
+            }
+            _ => panic!("expected an unknown transfer"),
+        }
+    }
+
+    #[test]
+    fn keeps_transfer_with_indexed_token_id_and_data_as_unknown() {
+        let log = transfer_log(&[TRANSFER_TOPIC, FROM, TO, VALUE], Some(VALUE.to_string()));
+        assert!(matches!(decode(&log), DecodedTransfer::Unknown(_)));
+    }
+
+    #[test]
+    fn keeps_transfer_without_data_as_unknown() {
+        let log = transfer_log(&[TRANSFER_TOPIC, FROM, TO], None);
+        assert!(matches!(decode(&log), DecodedTransfer::Unknown(_)));
+    }
+
+    #[test]
+    fn keeps_unindexed_transfers_of_the_wallets_only() {
+        let data = format!("0x{}{}{}", &FROM[2..], &TO[2..], &VALUE[2..]);
+        let transfer = decode(&transfer_log(&[TRANSFER_TOPIC], Some(data)));
+        let wallet = |topic: &str| vec![format!("0x{}", &topic[26..])];
+        assert!(transfer.involves(&wallet(FROM), &[]));
+        assert!(transfer.involves(&wallet(&TO.to_uppercase()), &[]));
+        assert!(!transfer.involves(&wallet(VALUE), &[]));
+    }
+
+    #[test]
+    fn keeps_unknown_transfers_with_a_wallet_topic_only() {
+        let data = format!("0x{}{}", &VALUE[2..], &VALUE[2..]);
+        let transfer = decode(&transfer_log(
+            &[TRANSFER_TOPIC, FROM, TO],
+            Some(data.clone()),
+        ));
+        assert!(transfer.involves(&[], &[TO.to_string()]));
+        assert!(!transfer.involves(&[], &[VALUE.to_string()]));
+
+        // Without indexed arguments nothing ties the transfer to a wallet
+        let transfer = decode(&transfer_log(&[TRANSFER_TOPIC], Some(data)));
+        assert!(!transfer.involves(&[], &[TRANSFER_TOPIC.to_string()]));
+    }
+
+    #[test]
+    fn reads_unindexed_transfers_of_the_named_contracts_only() {
+        const KITTIES: &str = "0x06012c8cf97bead5deae237070f9587f8e7a266d";
+        let topics = [FROM.to_string()];
+        let mut query = WalletQuery::new();
+        query.erc721_transfers = true;
+        // Without contracts every selection is filtered by a wallet topic
+        let selections = log_selections(&query, &topics);
+        assert_eq!(selections.len(), 4);
+        assert!(selections
+            .iter()
+            .all(|selection| selection.to_string().contains(FROM)));
+
+        query.unindexed_contracts = vec![KITTIES.to_string()];
+        let selections = log_selections(&query, &topics);
+        assert_eq!(selections.len(), 5);
+        assert_eq!(selections[4]["address"], serde_json::json!([KITTIES]));
+
+        query.erc721_transfers = false;
+        assert_eq!(log_selections(&query, &topics).len(), 4);
+    }
+
+    // A client error with the errors it was caused by, outermost first
+    #[derive(Debug)]
+    struct ClientError(String, Option<Box<dyn Error + Send + Sync>>);
+
+    impl fmt::Display for ClientError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl Error for ClientError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            self.1
+                .as_deref()
+                .map(|cause| cause as &(dyn Error + 'static))
+        }
+    }
+
+    fn context(message: &str, cause: Box<dyn Error + Send + Sync>) -> Box<dyn Error + Send + Sync> {
+        Box::new(ClientError(message.to_string(), Some(cause)))
+    }
+
+    // Error of a failed request as the client returns it, a context around the messages of
+    // its attempts, the latest first
+    fn client_error(message: &str, attempts: &[&str]) -> Box<dyn Error + Send + Sync> {
+        let root: Box<dyn Error + Send + Sync> = Box::new(ClientError(String::new(), None));
+        let err = attempts
+            .iter()
+            .rev()
+            .fold(root, |err, attempt| context(attempt, err));
+        context(message, err)
+    }
+
+    const STATUS_ATTEMPT: &str =
+        "http response status code 503 Service Unavailable, err body: overloaded";
+    const TIMEOUT_ATTEMPT: &str =
+        "execute http req\n\nCaused by:\n    0: error sending request\n    1: operation timed out";
+
+    #[test]
+    fn classifies_client_errors_by_the_latest_attempt() {
+        let err = QueryError::from_client(client_error(
+            "get initial data",
+            &[STATUS_ATTEMPT, TIMEOUT_ATTEMPT],
+        ));
+        assert!(matches!(err, QueryError::Server(_)));
+        assert!(err.message().contains("503"));
+
+        // A timeout after the server answered with an error status is a connection error
+        let err =
+            QueryError::from_client(client_error("get data", &[TIMEOUT_ATTEMPT, STATUS_ATTEMPT]));
+        assert!(matches!(err, QueryError::Transport(_)));
+
+        let err = QueryError::from_client(client_error(
+            "get data",
+            &["read response body bytes\n\nCaused by:\n    connection reset by peer"],
+        ));
+        assert!(matches!(err, QueryError::Transport(_)));
+
+        let err = QueryError::from_client(client_error(
+            "get data",
+            &["parse query response\n\nCaused by:\n    invalid message"],
+        ));
+        assert!(matches!(err, QueryError::Decode(_)));
+
+        // Errors without a failed attempt, e.g. of the stream itself, are connection errors
+        let err = QueryError::from_client(client_error("get height", &[]));
+        assert!(matches!(err, QueryError::Transport(_)));
+    }
+
+    #[tokio::test]
+    async fn classifies_reqwest_errors_by_kind() {
+        // A server answering every request with 503
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await;
+                let _ = socket
+                    .write_all(b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n")
+                    .await;
+            }
+        });
+        let status_err = reqwest::get(&url)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap_err();
+        let err = QueryError::from_client(context("get data", Box::new(status_err)));
+        assert!(matches!(err, QueryError::Server(_)));
+
+        // Nothing listens on the port once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let connect_err = reqwest::get(&url).await.unwrap_err();
+        let err = QueryError::from_client(context("get data", Box::new(connect_err)));
+        assert!(matches!(err, QueryError::Transport(_)));
+    }
+
+    #[tokio::test]
+    async fn aborts_the_query_task_when_dropped() {
+        let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(async move {
+            let _sender = sender;
+            std::future::pending::<()>().await
+        });
+        drop(AbortOnDrop(task));
+        // The aborted task drops the sender
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), receiver).await;
+        assert!(result.expect("the task was not aborted").is_err());
     }
 }
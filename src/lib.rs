@@ -0,0 +1,11 @@
+// Querying, decoding and caching of wallet transfers, shared by the TUI and headless tools
+pub mod address_book;
+pub mod cache;
+pub mod config;
+pub mod ens;
+pub mod export;
+pub mod history;
+pub mod hypersync;
+pub mod query;
+pub mod tokens;
+pub mod transfers;
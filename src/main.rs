@@ -1,25 +1,66 @@
 mod app;
-mod hypersync;
+mod cli;
+mod tui;
 mod ui;
 
-use app::{App, Chain, CurrentScreen};
+use app::{App, CurrentScreen, QueryEvent, QueryProgress, ScrollbarStates, TableStates};
+use clap::Parser;
+use cli::{Cli, Command};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode},
+    event::{self, EnableMouseCapture, Event, KeyCode},
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{enable_raw_mode, EnterAlternateScreen},
+};
+use futures::StreamExt;
+use hypertui::{
+    address_book::AddressBook,
+    cache::{self, TransferKinds},
+    config::Config,
+    ens::{self, EnsNames},
+    history::History,
+    hypersync::{self, QueryError},
+    query::{parse_block_spec, BlockSpec},
+    tokens::TokenRegistry,
+    transfers::Transfers,
 };
 use ratatui::prelude::{CrosstermBackend, Terminal};
-use serde_json;
-use std::fs::File;
 use std::{
     error::Error,
-    io::{self, BufWriter, Stdout, Write},
+    io::{self, Stdout},
+    pin::pin,
+    time::Duration,
 };
+use tokio::sync::mpsc;
 use ui::render_ui;
 
+const TICK_RATE: Duration = Duration::from_millis(100);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    let cli = Cli::parse();
+
+    // Read before entering the TUI so a malformed file is reported on the terminal
+    let config = Config::load()?;
+    if let Some(Command::Query {
+        query,
+        format,
+        output,
+    }) = &cli.command
+    {
+        return cli::run_query(&config, query, *format, output.clone()).await;
+    }
+    let address_book = AddressBook::load()?;
+    let ens = EnsNames::load()?;
+    let history = History::load()?;
+
+    let mut query = config.default_query();
+    cli.query.apply(&mut query, config.chains())?;
+    cli::resolve_ens_names(&mut query, &ens).await;
+
+    tui::install_panic_hook();
+
     enable_raw_mode()?;
+    let _guard = tui::TerminalGuard;
     let mut stdout = io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
 
@@ -27,16 +68,22 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let mut terminal = Terminal::new(backend)?;
 
     let mut app = App::new();
+    app.query = query;
+    app.config = config;
+    app.address_book = address_book;
+    app.ens = ens;
+    app.history = history;
 
-    let _res =run_app(&mut terminal, &mut app).await;
+    // Addresses on the command line skip the builder, unless the query needs fixing
+    if cli.query.has_addresses() {
+        match app.query.is_valid() {
+            true => start_query(&mut app),
+            false => app.current_screen = CurrentScreen::QueryBuilder,
+        }
+    }
+
+    let _res = run_app(&mut terminal, &mut app).await;
 
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
     Ok(())
 }
 
@@ -45,12 +92,20 @@ async fn run_app<'a>(
     app: &mut App<'a>,
 ) -> io::Result<bool> {
     loop {
+        tui::set_crash_query(&app.query);
         terminal.draw(|frame| render_ui(frame, app))?;
 
         if let CurrentScreen::Loading = &app.current_screen {
-            hypersync::query(app).await;
-            app.set_scrollbar_states();
-            app.current_screen = CurrentScreen::Main;
+            app.receive_query_events();
+        }
+        app.receive_resolved_dates();
+        app.receive_token_metadata();
+        app.receive_ens_names();
+        app.receive_ens_addresses();
+
+        // Redraw on every tick so results keep coming in while no key is pressed
+        if !event::poll(TICK_RATE)? {
+            continue;
         }
 
         if let Event::Key(key) = event::read()? {
@@ -58,7 +113,14 @@ async fn run_app<'a>(
                 continue;
             }
 
-            if !app.currently_editing && !app.is_exiting {
+            if !app.currently_editing
+                && !app.is_exiting
+                && !app.is_editing_label
+                && !app.is_searching
+                && !app.is_saving_query
+                && !app.is_exporting
+                && !matches!(app.current_screen, CurrentScreen::Loading)
+            {
                 match key.code {
                     KeyCode::Char('q') => app.is_exiting = true,
                     _ => {}
@@ -73,17 +135,101 @@ async fn run_app<'a>(
                 }
             }
 
-            if app.is_saving_json {
+            if app.is_confirming_overwrite {
+                match key.code {
+                    KeyCode::Char('y') => app.export(),
+                    KeyCode::Char('n') | KeyCode::Esc => app.is_confirming_overwrite = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            if app.is_exporting {
+                match key.code {
+                    KeyCode::Enter => app.export(),
+                    KeyCode::Esc => app.is_exporting = false,
+                    KeyCode::Left => {
+                        app.select_export_format(app.export_format.previous());
+                    }
+                    KeyCode::Right | KeyCode::Tab => {
+                        app.select_export_format(app.export_format.next());
+                    }
+                    KeyCode::Char(value) => app.export_path.push(value),
+                    KeyCode::Backspace => {
+                        app.export_path.pop();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            if app.is_cancelling {
                 match key.code {
                     KeyCode::Char('y') => {
-                        write_to_json(app)?;
-                        app.is_saving_json = false;
+                        app.partial_to_block =
+                            Some(app.query_progress.next_block.saturating_sub(1));
+                        app.current_screen = CurrentScreen::Main;
+                        app.is_cancelling = false;
                     }
                     KeyCode::Char('n') => {
-                        app.is_saving_json = false;
+                        app.transfers = Transfers::new();
+                        app.scrollbar_states = ScrollbarStates::new();
+                        app.current_screen = CurrentScreen::QueryBuilder;
+                        app.is_cancelling = false;
                     }
                     _ => {}
                 }
+                continue;
+            }
+
+            if app.is_editing_label {
+                match key.code {
+                    KeyCode::Enter => app.save_label(),
+                    KeyCode::Esc => app.is_editing_label = false,
+                    KeyCode::Char(value) => app.label_input.push(value),
+                    KeyCode::Backspace => {
+                        app.label_input.pop();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            if app.is_searching {
+                match key.code {
+                    KeyCode::Enter => app.is_searching = !app.search_next(),
+                    KeyCode::Esc => app.is_searching = false,
+                    KeyCode::Char(value) => app.search_input.push(value),
+                    KeyCode::Backspace => {
+                        app.search_input.pop();
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+
+            if app.is_selecting_chain {
+                match key.code {
+                    KeyCode::Up => app.chain_state.select_previous(),
+                    KeyCode::Down => app.chain_state.select_next(),
+                    KeyCode::Enter => app.select_chain(),
+                    KeyCode::Esc => app.is_selecting_chain = false,
+                    _ => {}
+                }
+                continue;
+            }
+
+            if app.is_saving_query {
+                match key.code {
+                    KeyCode::Enter => app.save_query(),
+                    KeyCode::Esc => app.is_saving_query = false,
+                    KeyCode::Char(value) => app.query_name_input.push(value),
+                    KeyCode::Backspace => {
+                        app.query_name_input.pop();
+                    }
+                    _ => {}
+                }
+                continue;
             }
 
             match app.current_screen {
@@ -91,14 +237,76 @@ async fn run_app<'a>(
                     KeyCode::Char('c') => {
                         app.current_screen = CurrentScreen::QueryBuilder;
                     }
+                    KeyCode::Up => {
+                        app.saved_query_state.select_previous();
+                    }
+                    KeyCode::Down => {
+                        app.saved_query_state.select_next();
+                    }
+                    KeyCode::Enter => {
+                        app.load_saved_query();
+                        resolve_dates(app);
+                        resolve_ens_addresses(app);
+                    }
+                    KeyCode::Char('h') => {
+                        open_history(app);
+                    }
+                    _ => {}
+                },
+                CurrentScreen::History => match key.code {
+                    KeyCode::Up => {
+                        app.previous_history_row();
+                    }
+                    KeyCode::Down => {
+                        app.next_history_row();
+                    }
+                    KeyCode::Enter => {
+                        if let Some(entry) = app.selected_history_entry() {
+                            app.query = entry.query.clone();
+                            start_query(app);
+                        }
+                    }
+                    KeyCode::Char('e') => {
+                        if let Some(entry) = app.selected_history_entry() {
+                            app.query = entry.query.clone();
+                            app.resolved_dates.clear();
+                            app.current_screen = CurrentScreen::QueryBuilder;
+                        }
+                    }
+                    KeyCode::Char('o') => {
+                        load_cached_results(app);
+                    }
+                    KeyCode::Esc | KeyCode::Char('c') => {
+                        app.current_screen = CurrentScreen::QueryBuilder;
+                    }
                     _ => {}
                 },
                 CurrentScreen::Main => match key.code {
                     KeyCode::Char('c') => {
                         app.current_screen = CurrentScreen::QueryBuilder;
                     }
+                    KeyCode::Char('h') => {
+                        open_history(app);
+                    }
                     KeyCode::Char('j') => {
-                        app.is_saving_json = true;
+                        app.start_export();
+                    }
+                    KeyCode::Char('t') => {
+                        app.show_local_time = !app.show_local_time;
+                    }
+                    // Runs the query again, which only scans blocks newer than the cached results
+                    KeyCode::Char('r') if app.query.is_valid() => {
+                        start_query(app);
+                    }
+                    KeyCode::Char('l') => {
+                        app.start_label_edit();
+                    }
+                    KeyCode::Char('/') => {
+                        app.search_error = None;
+                        app.is_searching = true;
+                    }
+                    KeyCode::Char('n') => {
+                        app.search_next();
                     }
                     KeyCode::Tab => {
                         app.transaction_tabs.next();
@@ -114,18 +322,28 @@ async fn run_app<'a>(
                 CurrentScreen::QueryBuilder => {
                     if !app.currently_editing {
                         match key.code {
-                            KeyCode::Char('y') => {
-                                app.current_screen = CurrentScreen::Loading;
+                            KeyCode::Char('y') if app.query.is_valid() => {
+                                start_query(app);
                             }
                             KeyCode::Char('e') => {
                                 app.currently_editing = true;
                             }
+                            KeyCode::Char('h') => {
+                                open_history(app);
+                            }
+                            KeyCode::Char('s') => {
+                                app.query_name_input.clear();
+                                app.query_name_error = None;
+                                app.is_saving_query = true;
+                            }
                             _ => {}
                         }
                     } else {
                         match key.code {
                             KeyCode::Esc => {
                                 app.currently_editing = false;
+                                resolve_dates(app);
+                                resolve_ens_addresses(app);
                             }
                             KeyCode::Up => {
                                 app.query_state.select_previous();
@@ -133,28 +351,24 @@ async fn run_app<'a>(
                             KeyCode::Down => {
                                 app.query_state.select_next();
                             }
-                            KeyCode::Char(value) => {
-                                if app.currently_editing {
-                                    match app.query_state.selected().unwrap() {
-                                        0 => app.query.address.push(value),
-                                        5 => app.query.start_block.push(value),
-                                        _ => {}
-                                    }
+                            KeyCode::Char(value) => match app.query_state.selected().unwrap() {
+                                0 => app.query.addresses.push(value),
+                                6 => app.query.start_block.push(value),
+                                7 => app.query.end_block.push(value),
+                                _ => {}
+                            },
+                            KeyCode::Backspace => match app.query_state.selected().unwrap() {
+                                0 => {
+                                    app.query.addresses.pop();
                                 }
-                            }
-                            KeyCode::Backspace => {
-                                if app.currently_editing {
-                                    match app.query_state.selected().unwrap() {
-                                        0 => {
-                                            app.query.address.pop();
-                                        }
-                                        5 => {
-                                            app.query.start_block.pop();
-                                        }
-                                        _ => {}
-                                    };
+                                6 => {
+                                    app.query.start_block.pop();
                                 }
-                            }
+                                7 => {
+                                    app.query.end_block.pop();
+                                }
+                                _ => {}
+                            },
                             KeyCode::Enter => match app.query_state.selected().unwrap() {
                                 0 => {
                                     app.query_state.select(Some(1));
@@ -169,17 +383,10 @@ async fn run_app<'a>(
                                     app.query.erc721_transfers = !app.query.erc721_transfers;
                                 }
                                 4 => {
-                                    app.query.chain = match app.query.chain {
-                                        Chain::Mainnet(_) => Chain::Optimism(
-                                            "https://optimism.hypersync.xyz".to_string(),
-                                        ),
-                                        Chain::Optimism(_) => Chain::Arbitrum(
-                                            "https://arbitrum.hypersync.xyz".to_string(),
-                                        ),
-                                        Chain::Arbitrum(_) => {
-                                            Chain::Mainnet("https://eth.hypersync.xyz".to_string())
-                                        }
-                                    };
+                                    app.query.erc1155_transfers = !app.query.erc1155_transfers;
+                                }
+                                5 => {
+                                    app.start_chain_select();
                                 }
                                 _ => {}
                             },
@@ -187,9 +394,30 @@ async fn run_app<'a>(
                         }
                     }
                 }
+                CurrentScreen::Error => match key.code {
+                    KeyCode::Enter | KeyCode::Char('c') => {
+                        app.current_screen = CurrentScreen::QueryBuilder;
+                    }
+                    KeyCode::Char('h') => {
+                        open_history(app);
+                    }
+                    _ => {}
+                },
                 CurrentScreen::Loading => match key.code {
-                    KeyCode::Char('q') => {
-                        app.current_screen = CurrentScreen::Startup;
+                    KeyCode::Char('q') | KeyCode::Esc => {
+                        app.cancel_query();
+                    }
+                    KeyCode::Char('t') => {
+                        app.show_local_time = !app.show_local_time;
+                    }
+                    KeyCode::Tab => {
+                        app.transaction_tabs.next();
+                    }
+                    KeyCode::Up => {
+                        app.previous_table_row();
+                    }
+                    KeyCode::Down => {
+                        app.next_table_row();
                     }
                     _ => {}
                 }
@@ -198,18 +426,129 @@ async fn run_app<'a>(
     }
 }
 
-fn write_to_json(app: &App) -> io::Result<()> {
-    let file = File::create(format!(
-        "outputs/{}-{}.json",
-        app.query.address,
-        match app.query.chain {
-            Chain::Mainnet(_) => "mainnet",
-            Chain::Optimism(_) => "optimism",
-            Chain::Arbitrum(_) => "arbitrum",
+fn start_query(app: &mut App) {
+    app.tokens = match TokenRegistry::load(app.query.chain.id) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            app.query_error = Some(QueryError::InvalidInput(err));
+            app.current_screen = CurrentScreen::Error;
+            return;
         }
-    ))?;
-    let mut writer = BufWriter::new(file);
-    serde_json::to_writer(&mut writer, &app.transfers)?;
-    writer.flush()?;
-    Ok(())
+    };
+
+    // Remove data from previous query
+    app.transfers = Transfers::new();
+    // Reset tables and scrollbars
+    app.table_states = TableStates::new();
+    app.scrollbar_states = ScrollbarStates::new();
+    // Clear the outcome of the previous query
+    app.partial_to_block = None;
+    app.query_error = None;
+
+    // Dates and relative blocks are resolved by the query, the first batch has the start block
+    app.query_progress = QueryProgress::new(0);
+
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let query = app.query.clone();
+    app.query_task = Some(tokio::spawn(async move {
+        // Aborting this task drops the stream, which stops the query
+        let mut batches = pin!(hypersync::fetch_transfers_cached(&query));
+        while let Some(batch) = batches.next().await {
+            let event = match batch {
+                Ok(batch) => QueryEvent::Batch(batch),
+                Err(err) => QueryEvent::Failed(err),
+            };
+            if sender.send(event).is_err() {
+                return;
+            }
+        }
+        let _ = sender.send(QueryEvent::Finished);
+    }));
+    app.query_receiver = Some(receiver);
+    app.current_screen = CurrentScreen::Loading;
+}
+
+fn open_history(app: &mut App) {
+    app.history_state.select(Some(0));
+    app.history_error = None;
+    app.current_screen = CurrentScreen::History;
+}
+
+// Shows the results of the selected history entry from the result cache, without a query
+fn load_cached_results(app: &mut App) {
+    let Some(entry) = app.selected_history_entry().cloned() else {
+        return;
+    };
+    if entry.next_block <= entry.from_block {
+        app.history_error = Some("This query did not scan any blocks".to_string());
+        return;
+    }
+    app.tokens = match TokenRegistry::load(entry.query.chain.id) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            app.history_error = Some(err);
+            return;
+        }
+    };
+
+    let addresses = entry.query.address_list();
+    let (resume_block, mut transfers) = cache::load_cached(
+        entry.query.chain.id,
+        &addresses,
+        entry.from_block,
+        Some(entry.next_block - 1),
+        TransferKinds::of(&entry.query),
+    );
+    if resume_block == entry.from_block {
+        app.history_error = Some("No cached results for this query".to_string());
+        return;
+    }
+    transfers.tag_internal(&addresses);
+
+    app.query = entry.query;
+    app.resolve_tokens(&transfers);
+    app.lookup_ens_names(&transfers);
+    app.transfers = transfers;
+    app.table_states = TableStates::new();
+    app.scrollbar_states = ScrollbarStates::new();
+    app.set_scrollbar_states();
+    app.query_error = None;
+    // The cache may cover only part of the range if it was refreshed from a later block
+    app.partial_to_block = match resume_block < entry.next_block {
+        true => Some(resume_block - 1),
+        false => None,
+    };
+    app.query_progress = QueryProgress::new(entry.from_block);
+    app.query_progress.next_block = resume_block;
+    app.current_screen = CurrentScreen::Main;
+}
+
+fn resolve_dates(app: &mut App) {
+    let is_date = |block: &str| matches!(parse_block_spec(block), Ok(BlockSpec::Date(_)));
+    if is_date(&app.query.start_block) || is_date(&app.query.end_block) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(hypersync::resolve_dates(app.query.clone(), sender));
+        app.date_receiver = Some(receiver);
+    }
+}
+
+// Resolves ENS names typed as wallet addresses, from the cache first and then over RPC
+fn resolve_ens_addresses(app: &mut App) {
+    let mut unresolved = Vec::new();
+    for name in app.query.ens_names() {
+        if app.query.ens_addresses.contains_key(&name) {
+            continue;
+        }
+        match app.ens.address_of(&name) {
+            Some(address) => {
+                app.query.ens_addresses.insert(name, address);
+            }
+            None => unresolved.push(name),
+        }
+    }
+    if let (Some(rpc_url), false) = (ens::rpc_url(), unresolved.is_empty()) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(ens::resolve_names(rpc_url, unresolved, sender));
+        app.name_receiver = Some(receiver);
+    }
 }
@@ -0,0 +1,467 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use ethers::{types::Address, utils::to_checksum};
+use serde::{Deserialize, Serialize};
+
+use crate::ens::is_ens_name;
+
+// A network served by HyperSync. The built-in chains can be replaced or extended with
+// [[chains]] entries in the config file.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Chain {
+    pub name: String,
+    pub url: String,
+    pub id: u64,
+    #[serde(default = "default_native_symbol")]
+    pub native_symbol: String,
+    // Transaction page with a {hash} placeholder, e.g. "https://etherscan.io/tx/{hash}"
+    #[serde(default)]
+    pub explorer_url: Option<String>,
+}
+
+fn default_native_symbol() -> String {
+    "ETH".to_string()
+}
+
+impl Chain {
+    fn new(name: &str, url: &str, id: u64, explorer_url: &str) -> Self {
+        Chain {
+            name: name.to_string(),
+            url: url.to_string(),
+            id,
+            native_symbol: default_native_symbol(),
+            explorer_url: Some(explorer_url.to_string()),
+        }
+    }
+
+    pub fn builtin() -> Vec<Chain> {
+        vec![
+            Chain::new(
+                "Mainnet",
+                "https://eth.hypersync.xyz",
+                1,
+                "https://etherscan.io/tx/{hash}",
+            ),
+            Chain::new(
+                "Optimism",
+                "https://optimism.hypersync.xyz",
+                10,
+                "https://optimistic.etherscan.io/tx/{hash}",
+            ),
+            Chain::new(
+                "Arbitrum",
+                "https://arbitrum.hypersync.xyz",
+                42161,
+                "https://arbiscan.io/tx/{hash}",
+            ),
+        ]
+    }
+
+    // Lowercase name with dashes, used in file names
+    pub fn slug(&self) -> String {
+        self.name
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect()
+    }
+
+    pub fn explorer_link(&self, hash: &str) -> Option<String> {
+        self.explorer_url
+            .as_ref()
+            .map(|template| template.replace("{hash}", hash))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WalletQuery {
+    // Comma separated, as typed in the query builder
+    pub addresses: String,
+    pub chain: Chain,
+    pub regular_transfers: bool,
+    pub erc20_transfers: bool,
+    pub erc721_transfers: bool,
+    pub erc1155_transfers: bool,
+    pub start_block: String,
+    pub end_block: String,
+    // Addresses of the ENS names typed in the query builder, keyed by lowercase name
+    #[serde(default)]
+    pub ens_addresses: HashMap<String, String>,
+    // ERC721 contracts like CryptoKitties whose Transfer events have no indexed arguments.
+    // HyperSync can't filter these by wallet, so all their Transfer events are read.
+    #[serde(default)]
+    pub unindexed_contracts: Vec<String>,
+}
+
+impl WalletQuery {
+    pub fn new() -> Self {
+        WalletQuery {
+            addresses: String::new(),
+            chain: Chain::builtin().remove(0),
+            regular_transfers: true,
+            erc20_transfers: true,
+            erc721_transfers: false,
+            erc1155_transfers: false,
+            start_block: String::from("1"),
+            end_block: String::from("latest"),
+            ens_addresses: HashMap::new(),
+            unindexed_contracts: Vec::new(),
+        }
+    }
+
+    // ENS names are replaced by their resolved address, unresolved names are left out
+    pub fn address_list(&self) -> Vec<String> {
+        self.addresses
+            .split(',')
+            .map(|address| address.trim().to_lowercase())
+            .filter(|address| !address.is_empty())
+            .filter_map(|address| match is_ens_name(&address) {
+                true => self.ens_addresses.get(&address).cloned(),
+                false => Some(address),
+            })
+            .collect()
+    }
+
+    pub fn ens_names(&self) -> Vec<String> {
+        self.addresses
+            .split(',')
+            .map(|address| address.trim().to_lowercase())
+            .filter(|address| is_ens_name(address))
+            .collect()
+    }
+
+    pub fn address_error(&self) -> Option<String> {
+        let addresses: Vec<&str> = self
+            .addresses
+            .split(',')
+            .map(|address| address.trim())
+            .filter(|address| !address.is_empty())
+            .collect();
+        if addresses.is_empty() {
+            return Some("at least one address is required".to_string());
+        }
+        addresses.iter().enumerate().find_map(|(i, address)| {
+            let result = match is_ens_name(address) {
+                true => match self.ens_addresses.contains_key(&address.to_lowercase()) {
+                    true => Ok(()),
+                    false => Err(format!("ENS name {} is not resolved", address)),
+                },
+                false => validate_address(address),
+            };
+            result.err().map(|err| match addresses.len() {
+                1 => err,
+                _ => format!("address {}: {}", i + 1, err),
+            })
+        })
+    }
+
+    pub fn start_block_error(&self) -> Option<String> {
+        if self.start_block.is_empty() {
+            return Some("block or date is required".to_string());
+        }
+        match parse_block_spec(&self.start_block) {
+            Ok(BlockSpec::Latest) => Some("must be a block number, '-N' or a date".to_string()),
+            Ok(_) => None,
+            Err(err) => Some(err),
+        }
+    }
+
+    pub fn end_block_error(&self) -> Option<String> {
+        let start = parse_block_spec(&self.start_block);
+        match parse_block_spec(&self.end_block) {
+            Ok(BlockSpec::Number(end)) => match start {
+                Ok(BlockSpec::Number(start)) if end < start => {
+                    Some("must not be before From Block".to_string())
+                }
+                _ => None,
+            },
+            Ok(BlockSpec::Date(end)) => match start {
+                Ok(BlockSpec::Date(start)) if end < start => {
+                    Some("must not be before From Block".to_string())
+                }
+                _ => None,
+            },
+            Ok(_) => None,
+            Err(err) => Some(err),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.address_error().is_none()
+            && self.start_block_error().is_none()
+            && self.end_block_error().is_none()
+    }
+}
+
+impl Default for WalletQuery {
+    fn default() -> Self {
+        WalletQuery::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BlockSpec {
+    Latest,
+    Number(u64),
+    // Number of blocks before the chain head, written as "-10000"
+    BehindHead(u64),
+    // Unix timestamp, resolved to the first block at or after it
+    Date(i64),
+}
+
+pub fn parse_block_spec(block: &str) -> Result<BlockSpec, String> {
+    let error = || "must be 'latest', a block number, '-N', a date or 'last 30d'".to_string();
+
+    if block.is_empty() || block == "latest" {
+        return Ok(BlockSpec::Latest);
+    }
+    if let Some(offset) = block.strip_prefix('-') {
+        return offset
+            .parse::<u64>()
+            .map(BlockSpec::BehindHead)
+            .map_err(|_| error());
+    }
+    if let Ok(number) = block.parse::<u64>() {
+        return Ok(BlockSpec::Number(number));
+    }
+    parse_date(block).map(BlockSpec::Date).ok_or_else(error)
+}
+
+// Accepts "2024-01-31", RFC 3339 datetimes and relative periods like "last 30d"
+fn parse_date(date: &str) -> Option<i64> {
+    if let Some(period) = date.strip_prefix("last ") {
+        let period = period.trim();
+        let unit = period.chars().last()?;
+        let seconds: i64 = match unit {
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let amount = period
+            .strip_suffix(unit)?
+            .parse::<i64>()
+            .ok()
+            .filter(|amount| *amount >= 0)?;
+        return Utc::now()
+            .timestamp()
+            .checked_sub(amount.checked_mul(seconds)?);
+    }
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+        return Some(datetime.timestamp());
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|datetime| datetime.and_utc().timestamp())
+}
+
+pub fn validate_address(address: &str) -> Result<(), String> {
+    let hex = match address.strip_prefix("0x") {
+        Some(hex) => hex,
+        None => return Err("must start with 0x".to_string()),
+    };
+    if hex.len() != 40 {
+        return Err(format!("must be 40 hex characters, got {}", hex.len()));
+    }
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("contains non-hex characters".to_string());
+    }
+
+    // All-lowercase and all-uppercase addresses carry no EIP-55 checksum
+    let is_mixed_case =
+        hex.chars().any(|c| c.is_ascii_lowercase()) && hex.chars().any(|c| c.is_ascii_uppercase());
+    if is_mixed_case {
+        let parsed = address.parse::<Address>().map_err(|err| err.to_string())?;
+        if to_checksum(&parsed, None) != address {
+            return Err("invalid EIP-55 checksum".to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_relative_periods() {
+        let now = Utc::now().timestamp();
+        let week_ago = parse_date("last 7d").unwrap();
+        assert!((now - 7 * 24 * 60 * 60 - week_ago).abs() <= 1);
+        let hours_ago = parse_date("last 12h").unwrap();
+        assert!((now - 12 * 60 * 60 - hours_ago).abs() <= 1);
+        let weeks_ago = parse_date("last 2w").unwrap();
+        assert!((now - 14 * 24 * 60 * 60 - weeks_ago).abs() <= 1);
+    }
+
+    #[test]
+    fn parses_absolute_dates() {
+        assert_eq!(parse_date("2024-01-31"), Some(1706659200));
+        assert_eq!(parse_date("2024-01-31T12:00:00Z"), Some(1706702400));
+        assert_eq!(parse_date("2024-01-31T12:00:00+02:00"), Some(1706695200));
+        assert_eq!(parse_date("2024-02-30"), None);
+        assert_eq!(parse_date("31/01/2024"), None);
+    }
+
+    #[test]
+    fn rejects_bad_periods() {
+        assert_eq!(parse_date("last 7m"), None);
+        assert_eq!(parse_date("last d"), None);
+        assert_eq!(parse_date("last -7d"), None);
+        assert_eq!(parse_date("last "), None);
+        assert_eq!(parse_date("last"), None);
+    }
+
+    #[test]
+    fn rejects_multibyte_periods() {
+        assert_eq!(parse_date("last 3é"), None);
+        assert_eq!(parse_date("last é"), None);
+        assert_eq!(parse_date("last 3d\u{301}"), None);
+    }
+
+    #[test]
+    fn rejects_overflowing_periods() {
+        assert_eq!(parse_date("last 99999999999999999w"), None);
+        assert_eq!(parse_date("last 9999999999999999h"), None);
+        assert_eq!(parse_date("last 9223372036854775807d"), None);
+    }
+
+    const CHECKSUMMED: &str = "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed";
+
+    fn query_with_addresses(addresses: &str) -> WalletQuery {
+        WalletQuery {
+            addresses: addresses.to_string(),
+            ..WalletQuery::new()
+        }
+    }
+
+    #[test]
+    fn accepts_checksummed_and_single_case_addresses() {
+        assert_eq!(validate_address(CHECKSUMMED), Ok(()));
+        assert_eq!(validate_address(&CHECKSUMMED.to_lowercase()), Ok(()));
+        let upper = format!("0x{}", CHECKSUMMED[2..].to_uppercase());
+        assert_eq!(validate_address(&upper), Ok(()));
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let swapped = CHECKSUMMED.replacen("aA", "Aa", 1);
+        assert_eq!(
+            validate_address(&swapped),
+            Err("invalid EIP-55 checksum".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        assert_eq!(
+            validate_address(&CHECKSUMMED[2..]),
+            Err("must start with 0x".to_string())
+        );
+        assert_eq!(
+            validate_address("0x1234"),
+            Err("must be 40 hex characters, got 4".to_string())
+        );
+        let non_hex = format!("0x{}", "g".repeat(40));
+        assert_eq!(
+            validate_address(&non_hex),
+            Err("contains non-hex characters".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_which_address_is_invalid() {
+        assert_eq!(
+            query_with_addresses(" , ").address_error(),
+            Some("at least one address is required".to_string())
+        );
+        assert_eq!(
+            query_with_addresses("0x1234").address_error(),
+            Some("must be 40 hex characters, got 4".to_string())
+        );
+        let addresses = format!("{}, 0x1234", CHECKSUMMED);
+        assert_eq!(
+            query_with_addresses(&addresses).address_error(),
+            Some("address 2: must be 40 hex characters, got 4".to_string())
+        );
+        assert_eq!(query_with_addresses(CHECKSUMMED).address_error(), None);
+    }
+
+    #[test]
+    fn requires_ens_names_to_be_resolved() {
+        let mut query = query_with_addresses("vitalik.eth");
+        assert_eq!(
+            query.address_error(),
+            Some("ENS name vitalik.eth is not resolved".to_string())
+        );
+        query
+            .ens_addresses
+            .insert("vitalik.eth".to_string(), CHECKSUMMED.to_lowercase());
+        assert_eq!(query.address_error(), None);
+        assert_eq!(query.address_list(), vec![CHECKSUMMED.to_lowercase()]);
+    }
+
+    fn query_with_blocks(start_block: &str, end_block: &str) -> WalletQuery {
+        WalletQuery {
+            start_block: start_block.to_string(),
+            end_block: end_block.to_string(),
+            ..WalletQuery::new()
+        }
+    }
+
+    #[test]
+    fn parses_block_specs() {
+        assert_eq!(parse_block_spec("latest"), Ok(BlockSpec::Latest));
+        assert_eq!(parse_block_spec(""), Ok(BlockSpec::Latest));
+        assert_eq!(
+            parse_block_spec("17000000"),
+            Ok(BlockSpec::Number(17000000))
+        );
+        assert_eq!(parse_block_spec("-10000"), Ok(BlockSpec::BehindHead(10000)));
+        assert_eq!(
+            parse_block_spec("2024-01-31"),
+            Ok(BlockSpec::Date(1706659200))
+        );
+        assert!(parse_block_spec("-").is_err());
+        assert!(parse_block_spec("--5").is_err());
+        assert!(parse_block_spec("12ab").is_err());
+    }
+
+    #[test]
+    fn validates_block_range() {
+        assert_eq!(query_with_blocks("100", "200").end_block_error(), None);
+        assert_eq!(query_with_blocks("100", "100").end_block_error(), None);
+        assert_eq!(
+            query_with_blocks("200", "100").end_block_error(),
+            Some("must not be before From Block".to_string())
+        );
+        assert_eq!(
+            query_with_blocks("2024-02-01", "2024-01-01").end_block_error(),
+            Some("must not be before From Block".to_string())
+        );
+        // Ranges mixing numbers, dates and offsets are checked once resolved
+        assert_eq!(
+            query_with_blocks("2024-02-01", "100").end_block_error(),
+            None
+        );
+        assert_eq!(query_with_blocks("200", "-100").end_block_error(), None);
+    }
+
+    #[test]
+    fn requires_a_start_block() {
+        assert_eq!(
+            query_with_blocks("", "latest").start_block_error(),
+            Some("block or date is required".to_string())
+        );
+        assert_eq!(
+            query_with_blocks("latest", "latest").start_block_error(),
+            Some("must be a block number, '-N' or a date".to_string())
+        );
+        assert_eq!(
+            query_with_blocks("-100", "latest").start_block_error(),
+            None
+        );
+    }
+}
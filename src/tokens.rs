@@ -0,0 +1,473 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use ethers::{
+    contract::abigen,
+    providers::{Http, Provider},
+    types::{Address, U256},
+    utils::format_units,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::query::Chain;
+
+// name and symbol are shared with ERC721 collections, which have no decimals
+abigen!(
+    Erc20Metadata,
+    r#"[
+        function name() external view returns (string)
+        function symbol() external view returns (string)
+        function decimals() external view returns (uint8)
+    ]"#
+);
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenMetadata {
+    pub symbol: String,
+    pub name: String,
+    pub decimals: Option<u8>,
+}
+
+impl TokenMetadata {
+    // Raw uint amount scaled by the token decimals, without trailing zeros
+    pub fn scale_amount(&self, amount: &str) -> Option<String> {
+        let amount = U256::from_dec_str(amount).ok()?;
+        let scaled = format_units(amount, self.decimals? as u32).ok()?;
+        match scaled.contains('.') {
+            true => Some(
+                scaled
+                    .trim_end_matches('0')
+                    .trim_end_matches('.')
+                    .to_string(),
+            ),
+            false => Some(scaled),
+        }
+    }
+
+    pub fn format_amount(&self, amount: &str) -> Option<String> {
+        self.scale_amount(amount)
+            .map(|scaled| format!("{} {}", scaled, self.symbol))
+    }
+
+    // Symbol for fungible tokens and name for collections, whichever is set
+    fn short_name(&self) -> Option<&str> {
+        let (first, second) = match self.decimals {
+            Some(_) => (&self.symbol, &self.name),
+            None => (&self.name, &self.symbol),
+        };
+        [first, second]
+            .into_iter()
+            .find(|name| !name.is_empty())
+            .map(|name| name.as_str())
+    }
+}
+
+// Entry of a Uniswap style token list, or a plain JSON array of the same objects
+#[derive(Deserialize)]
+struct TokenListEntry {
+    address: String,
+    symbol: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    decimals: Option<u8>,
+    #[serde(rename = "chainId")]
+    chain_id: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenListFile {
+    TokenList { tokens: Vec<TokenListEntry> },
+    Entries(Vec<TokenListEntry>),
+}
+
+// Token metadata for one chain, keyed by lowercase contract address
+pub struct TokenRegistry {
+    chain_id: u64,
+    tokens: HashMap<String, TokenMetadata>,
+    pending: HashSet<String>,
+    failed: HashSet<String>,
+}
+
+impl TokenRegistry {
+    pub fn new(chain_id: u64) -> Self {
+        TokenRegistry {
+            chain_id,
+            tokens: HashMap::new(),
+            pending: HashSet::new(),
+            failed: HashSet::new(),
+        }
+    }
+
+    // Loads the disk cache, then the token list from HYPERTUI_TOKEN_LIST which
+    // takes precedence over cached RPC lookups
+    pub fn load(chain_id: u64) -> Result<Self, String> {
+        let mut registry = TokenRegistry::new(chain_id);
+        if let Some(path) = cache_path(chain_id) {
+            if let Ok(content) = fs::read_to_string(path) {
+                if let Ok(tokens) = serde_json::from_str(&content) {
+                    registry.tokens = tokens;
+                }
+            }
+        }
+        if let Ok(path) = env::var("HYPERTUI_TOKEN_LIST") {
+            for (address, metadata) in read_token_list(Path::new(&path), chain_id)? {
+                registry.tokens.insert(address, metadata);
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn get(&self, contract: &str) -> Option<&TokenMetadata> {
+        self.tokens.get(&contract.to_lowercase())
+    }
+
+    // Short name for charts: the token symbol or collection name
+    pub fn contract_name(&self, contract: &str) -> Option<&str> {
+        self.get(contract).and_then(|token| token.short_name())
+    }
+
+    // Longer description for the details panel, e.g. "USD Coin (USDC)"
+    pub fn describe(&self, contract: &str) -> Option<String> {
+        match self.get(contract) {
+            Some(token) if !token.name.is_empty() && !token.symbol.is_empty() => {
+                Some(format!("{} ({})", token.name, token.symbol))
+            }
+            Some(token) => token.short_name().map(|name| name.to_string()),
+            None => None,
+        }
+    }
+
+    // Contracts that are neither known nor already looked up, marked as pending
+    pub fn unresolved<'a>(&mut self, contracts: impl Iterator<Item = &'a String>) -> Vec<String> {
+        let mut unresolved = Vec::new();
+        for contract in contracts {
+            let contract = contract.to_lowercase();
+            if !self.tokens.contains_key(&contract)
+                && !self.failed.contains(&contract)
+                && self.pending.insert(contract.clone())
+            {
+                unresolved.push(contract);
+            }
+        }
+        unresolved
+    }
+
+    pub fn resolve(&mut self, contract: String, metadata: Option<TokenMetadata>) {
+        self.pending.remove(&contract);
+        match metadata {
+            Some(metadata) => {
+                self.tokens.insert(contract, metadata);
+            }
+            // Contracts that don't implement the metadata calls are not retried
+            None => {
+                self.failed.insert(contract);
+            }
+        }
+    }
+
+    pub fn is_resolving(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    pub fn save_cache(&self) -> Result<(), String> {
+        let path = cache_path(self.chain_id).ok_or("no cache directory")?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+        }
+        let content = serde_json::to_string(&self.tokens).map_err(|err| err.to_string())?;
+        fs::write(path, content).map_err(|err| err.to_string())
+    }
+}
+
+fn cache_path(chain_id: u64) -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| {
+        dir.join("hypertui")
+            .join(format!("tokens-{}.json", chain_id))
+    })
+}
+
+// Reads a JSON token list or a CSV file with an address,symbol,name,decimals header,
+// where decimals are left empty for NFT collections
+fn read_token_list(path: &Path, chain_id: u64) -> Result<Vec<(String, TokenMetadata)>, String> {
+    let content = fs::read_to_string(path)
+        .map_err(|err| format!("could not read token list {}: {}", path.display(), err))?;
+
+    let entries = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("csv") => parse_csv_token_list(&content)
+            .map_err(|err| format!("token list {}: {}", path.display(), err))?,
+        _ => match serde_json::from_str(&content)
+            .map_err(|err| format!("token list {}: {}", path.display(), err))?
+        {
+            TokenListFile::TokenList { tokens } => tokens,
+            TokenListFile::Entries(entries) => entries,
+        },
+    };
+
+    Ok(entries
+        .into_iter()
+        .filter(|entry| entry.chain_id.unwrap_or(chain_id) == chain_id)
+        .map(|entry| {
+            (
+                entry.address.to_lowercase(),
+                TokenMetadata {
+                    symbol: entry.symbol,
+                    name: entry.name,
+                    decimals: entry.decimals,
+                },
+            )
+        })
+        .collect())
+}
+
+fn parse_csv_token_list(content: &str) -> Result<Vec<TokenListEntry>, String> {
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let header: Vec<String> = match lines.next() {
+        Some((_, line)) => line
+            .split(',')
+            .map(|column| column.trim().to_lowercase())
+            .collect(),
+        None => return Ok(Vec::new()),
+    };
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| format!("missing '{}' column", name))
+    };
+    let (address, symbol) = (column("address")?, column("symbol")?);
+    let name = column("name").ok();
+    let decimals = column("decimals").ok();
+    let chain_id = column("chainid").ok();
+
+    lines
+        .map(|(i, line)| {
+            let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+            let field = |index: usize| {
+                fields
+                    .get(index)
+                    .copied()
+                    .ok_or_else(|| format!("line {}: missing fields", i + 1))
+            };
+            Ok(TokenListEntry {
+                address: field(address)?.to_string(),
+                symbol: field(symbol)?.to_string(),
+                name: name.map(field).transpose()?.unwrap_or_default().to_string(),
+                decimals: match decimals.map(field).transpose()? {
+                    Some(decimals) if !decimals.is_empty() => Some(
+                        decimals
+                            .parse()
+                            .map_err(|_| format!("line {}: invalid decimals", i + 1))?,
+                    ),
+                    _ => None,
+                },
+                chain_id: match chain_id.map(field).transpose()? {
+                    Some(id) if !id.is_empty() => Some(
+                        id.parse()
+                            .map_err(|_| format!("line {}: invalid chainId", i + 1))?,
+                    ),
+                    _ => None,
+                },
+            })
+        })
+        .collect()
+}
+
+// JSON-RPC endpoint used to look up contracts missing from the list and cache,
+// e.g. HYPERTUI_MAINNET_RPC_URL
+pub fn rpc_url(chain: &Chain) -> Option<String> {
+    let name = chain.slug().to_uppercase().replace('-', "_");
+    env::var(format!("HYPERTUI_{}_RPC_URL", name)).ok()
+}
+
+pub async fn resolve_tokens(
+    rpc_url: String,
+    contracts: Vec<String>,
+    sender: UnboundedSender<(String, Option<TokenMetadata>)>,
+) {
+    let provider = Provider::<Http>::try_from(rpc_url.as_str())
+        .ok()
+        .map(Arc::new);
+    for contract in contracts {
+        let metadata = match &provider {
+            Some(provider) => fetch_metadata(provider.clone(), &contract).await,
+            None => None,
+        };
+        if sender.send((contract, metadata)).is_err() {
+            return;
+        }
+    }
+}
+
+async fn fetch_metadata(provider: Arc<Provider<Http>>, contract: &str) -> Option<TokenMetadata> {
+    let token = Erc20Metadata::new(contract.parse::<Address>().ok()?, provider);
+    // name and symbol are optional in every standard, but a contract without either is unnamed
+    let symbol = token.symbol().call().await.ok();
+    let name = token.name().call().await.ok();
+    if symbol.is_none() && name.is_none() {
+        return None;
+    }
+    Some(TokenMetadata {
+        symbol: symbol.unwrap_or_default(),
+        name: name.unwrap_or_default(),
+        decimals: token.decimals().call().await.ok(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USDC: &str = "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48";
+
+    fn token(symbol: &str, name: &str, decimals: Option<u8>) -> TokenMetadata {
+        TokenMetadata {
+            symbol: symbol.to_string(),
+            name: name.to_string(),
+            decimals,
+        }
+    }
+
+    #[test]
+    fn scales_amounts_by_decimals() {
+        let usdc = token("USDC", "USD Coin", Some(6));
+        assert_eq!(usdc.scale_amount("1500000").as_deref(), Some("1.5"));
+        assert_eq!(usdc.scale_amount("2000000").as_deref(), Some("2"));
+        assert_eq!(usdc.scale_amount("1").as_deref(), Some("0.000001"));
+        assert_eq!(usdc.format_amount("1500000").as_deref(), Some("1.5 USDC"));
+        assert_eq!(usdc.scale_amount("not a number"), None);
+    }
+
+    #[test]
+    fn leaves_amounts_without_decimals_unscaled() {
+        let collection = token("KITTY", "CryptoKitties", None);
+        assert_eq!(collection.scale_amount("5"), None);
+        assert_eq!(collection.format_amount("5"), None);
+    }
+
+    #[test]
+    fn names_tokens_by_symbol_and_collections_by_name() {
+        let mut registry = TokenRegistry::new(1);
+        registry.resolve(
+            USDC.to_lowercase(),
+            Some(token("USDC", "USD Coin", Some(6))),
+        );
+        registry.resolve("0x01".to_string(), Some(token("CK", "CryptoKitties", None)));
+        registry.resolve(
+            "0x02".to_string(),
+            Some(token("", "Unnamed Symbol", Some(18))),
+        );
+        registry.resolve("0x03".to_string(), Some(token("ANON", "", None)));
+
+        // Lookups ignore the address case
+        assert_eq!(registry.contract_name(USDC), Some("USDC"));
+        assert_eq!(registry.describe(USDC).as_deref(), Some("USD Coin (USDC)"));
+        assert_eq!(registry.contract_name("0x01"), Some("CryptoKitties"));
+        assert_eq!(registry.contract_name("0x02"), Some("Unnamed Symbol"));
+        assert_eq!(registry.describe("0x02").as_deref(), Some("Unnamed Symbol"));
+        assert_eq!(registry.contract_name("0x03"), Some("ANON"));
+        assert_eq!(registry.describe("0x03").as_deref(), Some("ANON"));
+    }
+
+    #[test]
+    fn falls_back_to_nothing_for_unknown_contracts() {
+        let mut registry = TokenRegistry::new(1);
+        registry.resolve("0x01".to_string(), Some(token("", "", Some(18))));
+        assert_eq!(registry.contract_name("0x01"), None);
+        assert_eq!(registry.describe("0x01"), None);
+        assert_eq!(registry.contract_name("0x02"), None);
+        assert_eq!(registry.describe("0x02"), None);
+    }
+
+    #[test]
+    fn looks_up_unresolved_contracts_once() {
+        let mut registry = TokenRegistry::new(1);
+        let contracts = [USDC.to_string(), USDC.to_lowercase(), "0x01".to_string()];
+
+        let unresolved = registry.unresolved(contracts.iter());
+        assert_eq!(unresolved, vec![USDC.to_lowercase(), "0x01".to_string()]);
+        assert!(registry.is_resolving());
+        // Pending contracts are not handed out again
+        assert!(registry.unresolved(contracts.iter()).is_empty());
+
+        registry.resolve(
+            USDC.to_lowercase(),
+            Some(token("USDC", "USD Coin", Some(6))),
+        );
+        registry.resolve("0x01".to_string(), None);
+        assert!(!registry.is_resolving());
+        // Neither known nor failed contracts are retried
+        assert!(registry.unresolved(contracts.iter()).is_empty());
+    }
+
+    #[test]
+    fn parses_csv_token_lists() {
+        let content = "Address, Symbol, Name, Decimals, ChainId\n\
+            0xA0b8,USDC,USD Coin,6,1\n\
+            \n\
+            0xB0b8,CK,CryptoKitties,,\n";
+        let entries = parse_csv_token_list(content).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].address, "0xA0b8");
+        assert_eq!(entries[0].symbol, "USDC");
+        assert_eq!(entries[0].name, "USD Coin");
+        assert_eq!(entries[0].decimals, Some(6));
+        assert_eq!(entries[0].chain_id, Some(1));
+        assert_eq!(entries[1].decimals, None);
+        assert_eq!(entries[1].chain_id, None);
+    }
+
+    #[test]
+    fn parses_csv_token_lists_with_only_required_columns() {
+        let entries = parse_csv_token_list("symbol,address\nUSDC,0xA0b8\n").unwrap();
+        assert_eq!(entries[0].address, "0xA0b8");
+        assert_eq!(entries[0].name, "");
+        assert_eq!(entries[0].decimals, None);
+        assert!(parse_csv_token_list("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_csv_token_lists() {
+        assert_eq!(
+            parse_csv_token_list("address,name\n0xA0b8,USD Coin\n").err(),
+            Some("missing 'symbol' column".to_string())
+        );
+        assert_eq!(
+            parse_csv_token_list("address,symbol,decimals\n0xA0b8,USDC,six\n").err(),
+            Some("line 2: invalid decimals".to_string())
+        );
+        assert_eq!(
+            parse_csv_token_list("address,symbol,decimals\n0xA0b8,USDC\n").err(),
+            Some("line 2: missing fields".to_string())
+        );
+    }
+
+    #[test]
+    fn filters_token_lists_by_chain() {
+        let path = env::temp_dir().join(format!("hypertui-tokens-{}.json", std::process::id()));
+        let content = r#"{"tokens": [
+            {"address": "0xA0B8", "symbol": "USDC", "name": "USD Coin", "decimals": 6, "chainId": 1},
+            {"address": "0xB0B8", "symbol": "USDC", "name": "USD Coin", "decimals": 6, "chainId": 10},
+            {"address": "0xC0C8", "symbol": "CK"}
+        ]}"#;
+        fs::write(&path, content).unwrap();
+        let tokens = read_token_list(&path, 1);
+        fs::remove_file(&path).unwrap();
+
+        let addresses: Vec<_> = tokens
+            .unwrap()
+            .into_iter()
+            .map(|(address, _)| address)
+            .collect();
+        assert_eq!(addresses, vec!["0xa0b8", "0xc0c8"]);
+    }
+}
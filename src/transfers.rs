@@ -0,0 +1,157 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Erc20Transfer {
+    pub hash: String,
+    pub block: String,
+    pub timestamp: Option<u64>,
+    pub contract: String,
+    pub to: String,
+    pub from: String,
+    pub amount: String,
+    pub internal: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Erc721Transfer {
+    pub hash: String,
+    pub block: String,
+    pub timestamp: Option<u64>,
+    pub contract: String,
+    pub to: String,
+    pub from: String,
+    pub token_id: String,
+    pub internal: bool,
+}
+
+// Transfer logs whose topics and data match neither ERC20 nor ERC721
+#[derive(Clone, Deserialize, Serialize)]
+pub struct UnknownTransfer {
+    pub hash: String,
+    pub block: String,
+    pub timestamp: Option<u64>,
+    pub contract: String,
+    pub topics: Vec<String>,
+    pub data: String,
+}
+
+impl UnknownTransfer {
+    // Addresses in the indexed topics after the event signature, usually from and to
+    pub fn indexed_addresses(&self) -> Vec<String> {
+        self.topics
+            .iter()
+            .skip(1)
+            .filter_map(|topic| topic.get(26..))
+            .map(|hex| format!("0x{}", hex))
+            .collect()
+    }
+}
+
+// TransferBatch logs are split into one transfer per id/amount pair
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Erc1155Transfer {
+    pub hash: String,
+    pub block: String,
+    pub timestamp: Option<u64>,
+    pub contract: String,
+    pub operator: String,
+    pub to: String,
+    pub from: String,
+    pub token_id: String,
+    pub amount: String,
+    pub internal: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RegularTransfer {
+    pub hash: String,
+    pub block_hash: String,
+    pub block: String,
+    pub timestamp: Option<u64>,
+    pub nonce: String,
+    pub to: String,
+    pub from: String,
+    pub value: String,
+    pub gas_used: String,
+    pub internal: bool,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Transfers {
+    pub regular_transfers: Vec<RegularTransfer>,
+    pub erc20_transfers: Vec<Erc20Transfer>,
+    pub erc721_transfers: Vec<Erc721Transfer>,
+    pub erc1155_transfers: Vec<Erc1155Transfer>,
+    pub unknown_transfers: Vec<UnknownTransfer>,
+}
+
+impl Transfers {
+    pub fn new() -> Self {
+        Transfers {
+            regular_transfers: Vec::new(),
+            erc20_transfers: Vec::new(),
+            erc721_transfers: Vec::new(),
+            erc1155_transfers: Vec::new(),
+            unknown_transfers: Vec::new(),
+        }
+    }
+
+    // Transfers between two of the queried wallets are internal
+    pub fn tag_internal(&mut self, addresses: &[String]) {
+        let is_own = |address: &str| addresses.contains(&address.to_lowercase());
+        for transfer in &mut self.regular_transfers {
+            transfer.internal = is_own(&transfer.from) && is_own(&transfer.to);
+        }
+        for transfer in &mut self.erc20_transfers {
+            transfer.internal = is_own(&transfer.from) && is_own(&transfer.to);
+        }
+        for transfer in &mut self.erc721_transfers {
+            transfer.internal = is_own(&transfer.from) && is_own(&transfer.to);
+        }
+        for transfer in &mut self.erc1155_transfers {
+            transfer.internal = is_own(&transfer.from) && is_own(&transfer.to);
+        }
+    }
+
+    // From and to addresses of every transfer with a known sender and recipient
+    pub fn addresses(&self) -> impl Iterator<Item = &String> {
+        let regular = self.regular_transfers.iter().map(|t| [&t.from, &t.to]);
+        let erc20 = self.erc20_transfers.iter().map(|t| [&t.from, &t.to]);
+        let erc721 = self.erc721_transfers.iter().map(|t| [&t.from, &t.to]);
+        let erc1155 = self.erc1155_transfers.iter().map(|t| [&t.from, &t.to]);
+        regular.chain(erc20).chain(erc721).chain(erc1155).flatten()
+    }
+
+    // Keeps the transfers for which keep(block, addresses) holds, where the addresses are the
+    // sender and recipient, or the indexed topics of unknown transfers
+    pub fn retain(&mut self, keep: impl Fn(u64, &[&str]) -> bool) {
+        let block = |block: &str| block.parse().unwrap_or(0);
+        self.regular_transfers
+            .retain(|t| keep(block(&t.block), &[&t.from, &t.to]));
+        self.erc20_transfers
+            .retain(|t| keep(block(&t.block), &[&t.from, &t.to]));
+        self.erc721_transfers
+            .retain(|t| keep(block(&t.block), &[&t.from, &t.to]));
+        self.erc1155_transfers
+            .retain(|t| keep(block(&t.block), &[&t.from, &t.to]));
+        self.unknown_transfers.retain(|t| {
+            let addresses = t.indexed_addresses();
+            let addresses: Vec<&str> = addresses.iter().map(|address| address.as_str()).collect();
+            keep(block(&t.block), &addresses)
+        });
+    }
+
+    pub fn extend(&mut self, other: Transfers) {
+        self.regular_transfers.extend(other.regular_transfers);
+        self.erc20_transfers.extend(other.erc20_transfers);
+        self.erc721_transfers.extend(other.erc721_transfers);
+        self.erc1155_transfers.extend(other.erc1155_transfers);
+        self.unknown_transfers.extend(other.unknown_transfers);
+    }
+}
+
+impl Default for Transfers {
+    fn default() -> Self {
+        Transfers::new()
+    }
+}
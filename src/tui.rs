@@ -0,0 +1,82 @@
+use std::{
+    backtrace::Backtrace,
+    fs::File,
+    io::{self, Write},
+    panic,
+    path::PathBuf,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crossterm::{
+    cursor,
+    event::DisableMouseCapture,
+    execute,
+    terminal::{disable_raw_mode, LeaveAlternateScreen},
+};
+
+use hypertui::query::WalletQuery;
+
+// Snapshot of the query being built or run, written to the crash log on panic
+static CRASH_QUERY: Mutex<Option<WalletQuery>> = Mutex::new(None);
+
+// Restores the terminal when dropped, including on early returns and unwinding
+pub struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+pub fn restore() {
+    let _ = disable_raw_mode();
+    let _ = execute!(
+        io::stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        cursor::Show
+    );
+}
+
+pub fn set_crash_query(query: &WalletQuery) {
+    if let Ok(mut crash_query) = CRASH_QUERY.lock() {
+        *crash_query = Some(query.clone());
+    }
+}
+
+pub fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        match write_crash_log(info) {
+            Ok(path) => eprintln!("hypertui crashed, details written to {}", path.display()),
+            Err(err) => eprintln!("hypertui crashed, could not write crash log: {}", err),
+        }
+        default_hook(info);
+    }));
+}
+
+fn write_crash_log(info: &panic::PanicHookInfo) -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let path = std::env::temp_dir().join(format!("hypertui-crash-{}.log", timestamp));
+
+    let mut file = File::create(&path)?;
+    writeln!(
+        file,
+        "hypertui {} crashed at {}",
+        env!("CARGO_PKG_VERSION"),
+        timestamp
+    )?;
+    writeln!(file, "\n{}", info)?;
+    // A panic while the lock is held must not block the hook
+    match CRASH_QUERY.try_lock() {
+        Ok(query) => writeln!(file, "\nQuery: {:#?}", *query)?,
+        Err(_) => writeln!(file, "\nQuery: unavailable")?,
+    }
+    writeln!(file, "\nBacktrace:\n{}", Backtrace::force_capture())?;
+    Ok(path)
+}
@@ -1,15 +1,24 @@
+use std::cmp::Reverse;
+
+use chrono::{DateTime, Local};
 use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout, Margin, Rect},
     style::{Color, Style, Stylize},
     text::{self, Line, Span, Text},
     widgets::{
-        Bar, BarChart, BarGroup, Block, Borders, Cell, Clear, HighlightSpacing, List, ListItem,
-        Padding, Paragraph, Row, Scrollbar, ScrollbarOrientation, Table, Tabs, Wrap,
+        Bar, BarChart, BarGroup, Block, Borders, Cell, Clear, Gauge, HighlightSpacing, List,
+        ListItem, Padding, Paragraph, Row, Scrollbar, ScrollbarOrientation, Table, Tabs, Wrap,
     },
     Frame,
 };
 
-use crate::app::{App, Chain, CurrentScreen, TransactionTab};
+use hypertui::{
+    export::ExportFormat,
+    history::QueryOutcome,
+    query::{parse_block_spec, BlockSpec},
+};
+
+use crate::app::{App, CurrentScreen, TransactionTab};
 
 pub fn render_ui(frame: &mut Frame, app: &mut App) {
     let centered_rect = centered_rect(95, 95, frame.area());
@@ -28,7 +37,7 @@ pub fn render_ui(frame: &mut Frame, app: &mut App) {
         .split(centered_rect);
 
     match app.current_screen {
-        CurrentScreen::Startup => render_startup_screen(frame, centered_rect),
+        CurrentScreen::Startup => render_startup_screen(frame, app, centered_rect),
         CurrentScreen::Main => {
             render_title(frame, app, chunks[0]);
             render_main_screen(frame, app, chunks[1]);
@@ -42,6 +51,17 @@ pub fn render_ui(frame: &mut Frame, app: &mut App) {
         CurrentScreen::Loading => {
             render_title(frame, app, chunks[0]);
             render_loading_screen(frame, app, chunks[1]);
+            render_footer(frame, app, chunks[2]);
+        }
+        CurrentScreen::Error => {
+            render_title(frame, app, chunks[0]);
+            render_error_screen(frame, app, chunks[1]);
+            render_footer(frame, app, chunks[2]);
+        }
+        CurrentScreen::History => {
+            render_title(frame, app, chunks[0]);
+            render_history_screen(frame, app, chunks[1]);
+            render_footer(frame, app, chunks[2]);
         }
     }
 
@@ -49,9 +69,31 @@ pub fn render_ui(frame: &mut Frame, app: &mut App) {
         render_exit_popup(frame, centered_rect);
     }
 
-    if app.is_saving_json {
-        render_json_popup(frame, centered_rect);
+    if app.is_exporting {
+        render_export_popup(frame, app, centered_rect);
+    }
+
+    if app.is_cancelling {
+        render_cancel_popup(frame, app, centered_rect);
+    }
+
+    if app.is_editing_label {
+        render_label_popup(frame, app, centered_rect);
+    }
+
+    if app.is_searching {
+        render_search_popup(frame, app, centered_rect);
     }
+
+    if app.is_saving_query {
+        render_save_query_popup(frame, app, centered_rect);
+    }
+
+    if app.is_selecting_chain {
+        render_chain_popup(frame, app, centered_rect);
+    }
+
+    render_toast(frame, app, centered_rect);
 }
 
 fn render_main_screen(frame: &mut Frame, app: &mut App, area: Rect) {
@@ -69,12 +111,8 @@ fn render_main_screen(frame: &mut Frame, app: &mut App, area: Rect) {
     let text = Text::from(Span::styled(
         format!(
             "{} | {}",
-            truncate(&app.query.address),
-            match app.query.chain {
-                Chain::Mainnet(_) => "Mainnet",
-                Chain::Optimism(_) => "Optimism",
-                Chain::Arbitrum(_) => "Arbitrum",
-            }
+            wallets_label(&app.query.address_list()),
+            app.query.chain.name
         ),
         Style::new().green(),
     ));
@@ -85,12 +123,22 @@ fn render_main_screen(frame: &mut Frame, app: &mut App, area: Rect) {
 
 fn render_title(frame: &mut Frame, app: &mut App, area: Rect) {
     let title_block = Block::default().style(Style::default());
-    let mut content = "";
+    let mut content = String::new();
 
     match app.current_screen {
-        CurrentScreen::QueryBuilder => content = "\n:: Create Query ::",
-        CurrentScreen::Loading => content = "\n:: Processing Query ::",
-        CurrentScreen::Main => content = "\n:: Query Results ::",
+        CurrentScreen::QueryBuilder => content = "\n:: Create Query ::".to_string(),
+        CurrentScreen::Loading => content = "\n:: Processing Query ::".to_string(),
+        CurrentScreen::Error => content = "\n:: Query Failed ::".to_string(),
+        CurrentScreen::History => content = "\n:: Query History ::".to_string(),
+        CurrentScreen::Main => match app.partial_to_block {
+            Some(block) => {
+                content = format!(
+                    "\n:: Query Results (Partial, scanned up to block {}) ::",
+                    block
+                )
+            }
+            None => content = "\n:: Query Results ::".to_string(),
+        },
         _ => {}
     }
 
@@ -101,63 +149,163 @@ fn render_title(frame: &mut Frame, app: &mut App, area: Rect) {
     frame.render_widget(title, area);
 }
 
+// The result tables fill in as batches arrive, below the progress of the query
 fn render_loading_screen(frame: &mut Frame, app: &mut App, area: Rect) {
-    let pop_up = centered_rect(60, 40, area);
-    let list_block = Block::default()
-        .style(Style::default().green())
-        .title(" Loading ")
-        .title_alignment(Alignment::Center)
-        .borders(Borders::ALL)
-        .padding(Padding::symmetric(4, 2));
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(7), Constraint::Min(1)])
+        .split(area);
 
-    let list_items = [
-        ListItem::new(Line::from(format!(
-            "Wallet Address:            {}",
-            app.query.address
-        ))),
-        ListItem::new(Line::from(format!(
-            "Regular Transfers:         {}",
-            match app.query.regular_transfers {
-                true => "\u{2714}",
-                false => "\u{2A2F}",
-            }
-        ))),
-        ListItem::new(Line::from(format!(
-            "ERC20 Transfers:           {}",
-            match app.query.erc20_transfers {
-                true => "\u{2714}",
-                false => "\u{2A2F}",
-            }
-        ))),
-        ListItem::new(Line::from(format!(
-            "ERC721 Transfers:          {}",
-            match app.query.erc721_transfers {
-                true => "\u{2714}",
-                false => "\u{2A2F}",
-            }
-        ))),
-        ListItem::new(Line::from(format!(
-            "Chain:                     {}",
-            match app.query.chain {
-                Chain::Mainnet(_) => "Mainnet",
-                Chain::Optimism(_) => "Optimism",
-                Chain::Arbitrum(_) => "Arbitrum",
+    render_query_progress(frame, app, chunks[0]);
+    render_main_screen(frame, app, chunks[1]);
+}
+
+fn render_query_progress(frame: &mut Frame, app: &mut App, area: Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .horizontal_margin(2)
+        .constraints([Constraint::Length(4), Constraint::Length(3)])
+        .split(area);
+
+    let progress = &app.query_progress;
+    let elapsed = progress.elapsed().as_secs();
+    let rows_per_second = match progress.elapsed().as_secs_f64() {
+        seconds if seconds > 0.0 => app.num_transfers() as f64 / seconds,
+        _ => 0.0,
+    };
+
+    let lines = vec![
+        Line::from(format!(
+            "From Block: {}  |  To Block: {}  |  Next Block: {}  |  Archive Height: {}",
+            with_progress_block(app, &app.query.start_block, Some(progress.from_block)),
+            with_progress_block(app, &app.query.end_block, progress.to_block),
+            match progress.is_resolved {
+                true => progress.next_block.to_string(),
+                false => "-".to_string(),
+            },
+            match progress.archive_height {
+                Some(height) => height.to_string(),
+                None => "-".to_string(),
             }
-        ))),
-        ListItem::new(Line::from(format!(
-            "From Block:                {}",
-            app.query.start_block,
-        ))),
+        )),
+        Line::from(format!(
+            "Transfers Found: {}  |  Elapsed: {:02}:{:02}  |  Throughput: {:.0} blocks/s | {:.1} rows/s",
+            app.num_transfers(),
+            elapsed / 60,
+            elapsed % 60,
+            progress.blocks_per_second(),
+            rows_per_second
+        )),
     ];
 
-    let list = List::new(list_items)
-        .block(list_block)
+    let paragraph = Paragraph::new(lines)
+        .block(
+            Block::default()
+                .style(Style::default().green())
+                .title(" Progress ")
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL)
+                .padding(Padding::horizontal(2)),
+        )
         .style(Style::new().yellow());
 
-    frame.render_widget(list, pop_up);
+    let gauge = Gauge::default()
+        .block(Block::default().borders(Borders::ALL).green())
+        .gauge_style(Style::new().yellow().on_dark_gray())
+        .ratio(progress.ratio())
+        .label(format!("{:.1}%", progress.ratio() * 100.0));
+
+    frame.render_widget(paragraph, chunks[0]);
+    frame.render_widget(gauge, chunks[1]);
+}
+
+fn render_error_screen(frame: &mut Frame, app: &mut App, area: Rect) {
+    let pop_up = centered_rect(60, 40, area);
+
+    let (title, message) = match &app.query_error {
+        Some(err) => (format!(" {} ", err.title()), err.message().to_string()),
+        None => (" Error ".to_string(), "Unknown error".to_string()),
+    };
+
+    let paragraph = Paragraph::new(Text::styled(message, Style::new().yellow()))
+        .block(
+            Block::default()
+                .style(Style::default().red())
+                .title(title)
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL)
+                .padding(Padding::symmetric(4, 2)),
+        )
+        .wrap(Wrap { trim: false });
+
+    frame.render_widget(paragraph, pop_up);
+}
+
+fn render_history_screen(frame: &mut Frame, app: &mut App, area: Rect) {
+    let mut block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::new().green())
+        .padding(Padding::horizontal(1));
+    if let Some(error) = &app.history_error {
+        block = block.title_bottom(Line::from(format!(" {} ", error)).red());
+    }
+
+    if app.history.is_empty() {
+        let paragraph = Paragraph::new(Text::styled(
+            "\nNo queries yet, run one from the query builder",
+            Style::default().fg(Color::Yellow),
+        ))
+        .block(block)
+        .alignment(Alignment::Center);
+        frame.render_widget(paragraph, area);
+        return;
+    }
+
+    let header = Row::new(vec![
+        "Executed", "Chain", "Wallets", "Blocks", "Rows", "Duration", "Outcome",
+    ])
+    .style(Style::new().bold().green());
+
+    let rows: Vec<Row> = app
+        .history
+        .recent()
+        .map(|entry| {
+            let outcome = match &entry.outcome {
+                QueryOutcome::Completed => "Completed".to_string(),
+                QueryOutcome::Cancelled => "Cancelled".to_string(),
+                QueryOutcome::Failed(err) => format!("Failed: {}", err),
+            };
+            Row::new(vec![
+                format_timestamp(Some(entry.executed_at as u64), app.show_local_time),
+                entry.query.chain.name.clone(),
+                wallets_label(&entry.query.address_list()),
+                format!("{} - {}", entry.query.start_block, entry.query.end_block),
+                entry.rows.total().to_string(),
+                format!("{:.1}s", entry.duration_ms as f64 / 1000.0),
+                outcome,
+            ])
+            .style(Style::new().yellow())
+        })
+        .collect();
+
+    let widths = [
+        Constraint::Length(19),
+        Constraint::Length(10),
+        Constraint::Length(16),
+        Constraint::Min(20),
+        Constraint::Length(8),
+        Constraint::Length(10),
+        Constraint::Min(10),
+    ];
+    let table = Table::new(rows, widths)
+        .header(header)
+        .block(block)
+        .highlight_style(Style::new().reversed());
+
+    frame.render_stateful_widget(table, area, &mut app.history_state);
 }
 
-fn render_startup_screen(frame: &mut Frame, area: Rect) {
+fn render_startup_screen(frame: &mut Frame, app: &mut App, area: Rect) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
@@ -186,24 +334,63 @@ fn render_startup_screen(frame: &mut Frame, area: Rect) {
     let instructions_block = Block::default().style(Style::default());
 
     let instructions = Paragraph::new(Text::styled(
-        "Press 'c' to start a new query\n\nPress 'q' to quit",
+        "Press 'c' to start a new query\n\nPress 'h' to browse query history\n\nPress 'q' to quit",
         Style::default().fg(Color::Yellow),
     ))
     .block(instructions_block)
     .alignment(Alignment::Center);
 
     frame.render_widget(title, chunks[1]);
-    frame.render_widget(instructions, chunks[2]);
+
+    let saved_queries = app.config.saved_queries();
+    if saved_queries.is_empty() {
+        frame.render_widget(instructions, chunks[2]);
+        return;
+    }
+
+    let bottom = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(6), Constraint::Min(1)])
+        .split(chunks[2]);
+    frame.render_widget(instructions, bottom[0]);
+
+    let list_items: Vec<ListItem> = saved_queries
+        .iter()
+        .map(|saved| {
+            ListItem::new(Line::from(Span::styled(
+                saved.name.clone(),
+                Style::default().fg(Color::Yellow),
+            )))
+        })
+        .collect();
+    let list = List::new(list_items)
+        .block(
+            Block::default()
+                .green()
+                .title(" Saved Queries: \u{21D1} / \u{21D3} to select, ENTER to open ")
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL),
+        )
+        .highlight_symbol("> ")
+        .highlight_spacing(HighlightSpacing::Always);
+    frame.render_stateful_widget(
+        list,
+        centered_rect(50, 100, bottom[1]),
+        &mut app.saved_query_state,
+    );
 }
 
 fn render_query_screen(frame: &mut Frame, app: &mut App, area: Rect) {
     let pop_up = centered_rect(60, 40, area);
 
     let list_items = vec![
-        ListItem::new(Line::from(Span::styled(
-            format!("Wallet Address:            {}", app.query.address),
-            Style::default().fg(Color::Yellow),
-        ))),
+        ListItem::new(validated_line(
+            format!(
+                "Wallet Addresses:          {}",
+                with_resolved_names(app, &app.query.addresses)
+            ),
+            app.query.address_error(),
+        )),
         ListItem::new(Line::from(Span::styled(
             format!(
                 "Regular Transfers:         {}",
@@ -236,39 +423,104 @@ fn render_query_screen(frame: &mut Frame, app: &mut App, area: Rect) {
         ))),
         ListItem::new(Line::from(Span::styled(
             format!(
-                "Chain:                     {}",
-                match app.query.chain {
-                    Chain::Mainnet(_) => "Mainnet",
-                    Chain::Optimism(_) => "Optimism",
-                    Chain::Arbitrum(_) => "Arbitrum",
+                "ERC1155 Transfers:         {}",
+                match app.query.erc1155_transfers {
+                    true => "Yes",
+                    false => "No",
                 }
             ),
             Style::default().fg(Color::Yellow),
         ))),
         ListItem::new(Line::from(Span::styled(
-            format!("From Block:                {}", app.query.start_block),
+            format!("Chain:                     {}", app.query.chain.name),
             Style::default().fg(Color::Yellow),
         ))),
+        ListItem::new(validated_line(
+            format!(
+                "From Block:                {}",
+                with_resolved_block(app, &app.query.start_block)
+            ),
+            app.query.start_block_error(),
+        )),
+        ListItem::new(validated_line(
+            format!(
+                "To Block:                  {}",
+                with_resolved_block(app, &app.query.end_block)
+            ),
+            app.query.end_block_error(),
+        )),
     ];
 
+    let mut block = Block::default()
+        .green()
+        .title(format!(
+            " Edit Mode: {} ",
+            if app.currently_editing { "ON" } else { "OFF" }
+        ))
+        .title_alignment(Alignment::Center)
+        .borders(Borders::ALL)
+        .padding(Padding::uniform(2));
+    if !app.query.is_valid() {
+        block =
+            block.title_bottom(Line::from(" Fix the highlighted fields to start the query ").red());
+    }
+
     let list = List::new(list_items)
         .highlight_symbol(if app.currently_editing { "> " } else { "  " })
         .highlight_spacing(HighlightSpacing::Always)
-        .block(
-            Block::default()
-                .green()
-                .title(format!(
-                    " Edit Mode: {} ",
-                    if app.currently_editing { "ON" } else { "OFF" }
-                ))
-                .title_alignment(Alignment::Center)
-                .borders(Borders::ALL)
-                .padding(Padding::uniform(2)),
-        );
+        .block(block);
 
     frame.render_stateful_widget(list, pop_up, &mut app.query_state);
 }
 
+fn with_progress_block(app: &App, block: &str, resolved: Option<u64>) -> String {
+    match resolved {
+        _ if !app.query_progress.is_resolved => format!("{} (resolving...)", block),
+        Some(number) if number.to_string() != block => format!("{} (#{})", block, number),
+        _ => block.to_string(),
+    }
+}
+
+fn with_resolved_block(app: &App, block: &str) -> String {
+    match parse_block_spec(block) {
+        Ok(BlockSpec::Date(_)) => match app.resolved_dates.get(block) {
+            Some(number) => format!("{} (#{})", block, number),
+            None if app.currently_editing => block.to_string(),
+            None => format!("{} (resolving...)", block),
+        },
+        _ => block.to_string(),
+    }
+}
+
+// ENS names typed as addresses, followed by the addresses they resolve to
+fn with_resolved_names(app: &App, addresses: &str) -> String {
+    let resolved: Vec<String> = app
+        .query
+        .ens_names()
+        .iter()
+        .filter_map(|name| app.query.ens_addresses.get(name))
+        .map(|address| truncate(address))
+        .collect();
+    match app.name_receiver {
+        Some(_) => format!("{} (resolving...)", addresses),
+        None if resolved.is_empty() || app.currently_editing => addresses.to_string(),
+        None => format!("{} ({})", addresses, resolved.join(", ")),
+    }
+}
+
+fn validated_line<'a>(content: String, error: Option<String>) -> Line<'a> {
+    match error {
+        Some(error) => Line::from(vec![
+            Span::styled(content, Style::default().fg(Color::Red)),
+            Span::styled(
+                format!("  \u{2A2F} {}", error),
+                Style::default().fg(Color::Red).italic(),
+            ),
+        ]),
+        None => Line::from(Span::styled(content, Style::default().fg(Color::Yellow))),
+    }
+}
+
 fn render_tabs(frame: &mut Frame, app: &mut App, area: Rect) {
     let tabs = app
         .transaction_tabs
@@ -312,6 +564,14 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
             .direction(Direction::Horizontal)
             .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
             .split(right_panel[1]),
+        TransactionTab::ERC1155 => Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(right_panel[1]),
+        TransactionTab::Unknown => Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(100)])
+            .split(right_panel[1]),
     };
 
     let header_style = Style::default().fg(Color::LightGreen).bg(Color::DarkGray);
@@ -320,7 +580,7 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
     match app.transaction_tabs.titles[app.transaction_tabs.index] {
         "Regular Transfers" => {
             if !app.transfers.regular_transfers.is_empty() {
-                let header = ["Hash", "From", "To", "Value"]
+                let header = ["Hash", "Time", "From", "To", "Value"]
                     .into_iter()
                     .map(Cell::from)
                     .collect::<Row>()
@@ -332,9 +592,15 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
                     .iter()
                     .enumerate()
                     .map(|(_i, data)| {
-                        let item = [&data.hash, &data.from, &data.to, &data.value[..5]];
+                        let item = [
+                            truncate(&data.hash),
+                            format_timestamp(data.timestamp, app.show_local_time),
+                            address_label(app, &data.from),
+                            address_label(app, &data.to),
+                            truncate(&data.value[..5]),
+                        ];
                         item.into_iter()
-                            .map(|content| Cell::from(Text::from(format!("{}", truncate(content)))))
+                            .map(|content| Cell::from(Text::from(content)))
                             .collect::<Row>()
                             .style(Style::new().fg(Color::Yellow).bg(Color::DarkGray))
                             .height(1)
@@ -344,6 +610,7 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
                     rows,
                     [
                         Constraint::Length(200),
+                        Constraint::Length(20),
                         Constraint::Length(40),
                         Constraint::Length(40),
                         Constraint::Length(40),
@@ -371,7 +638,7 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
         }
         "ERC20 Transfers" => {
             if !app.transfers.erc20_transfers.is_empty() {
-                let header = ["Hash", "From", "To", "Value"]
+                let header = ["Hash", "Time", "From", "To", "Value"]
                     .into_iter()
                     .map(Cell::from)
                     .collect::<Row>()
@@ -383,9 +650,15 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
                     .iter()
                     .enumerate()
                     .map(|(_i, data)| {
-                        let item = [&data.hash, &data.from, &data.to, &data.amount];
+                        let item = [
+                            truncate(&data.hash),
+                            format_timestamp(data.timestamp, app.show_local_time),
+                            address_label(app, &data.from),
+                            address_label(app, &data.to),
+                            truncate(&app.erc20_amount(data)),
+                        ];
                         item.into_iter()
-                            .map(|content| Cell::from(Text::from(format!("{}", truncate(content)))))
+                            .map(|content| Cell::from(Text::from(content)))
                             .collect::<Row>()
                             .style(Style::new().fg(Color::Yellow).bg(Color::DarkGray))
                             .height(1)
@@ -394,6 +667,7 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
                     rows,
                     [
                         Constraint::Length(200),
+                        Constraint::Length(20),
                         Constraint::Length(40),
                         Constraint::Length(40),
                         Constraint::Length(40),
@@ -420,7 +694,7 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
         }
         "ERC721 Transfers" => {
             if !app.transfers.erc721_transfers.is_empty() {
-                let header = ["Hash", "From", "To", "TokenId"]
+                let header = ["Hash", "Time", "From", "To", "TokenId"]
                     .into_iter()
                     .map(Cell::from)
                     .collect::<Row>()
@@ -432,9 +706,15 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
                     .iter()
                     .enumerate()
                     .map(|(_i, data)| {
-                        let item = [&data.hash, &data.from, &data.to, &data.token_id];
+                        let item = [
+                            truncate(&data.hash),
+                            format_timestamp(data.timestamp, app.show_local_time),
+                            address_label(app, &data.from),
+                            address_label(app, &data.to),
+                            truncate(&data.token_id),
+                        ];
                         item.into_iter()
-                            .map(|content| Cell::from(Text::from(format!("{}", truncate(content)))))
+                            .map(|content| Cell::from(Text::from(content)))
                             .collect::<Row>()
                             .style(Style::new().fg(Color::Yellow).bg(Color::DarkGray))
                             .height(1)
@@ -443,6 +723,7 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
                     rows,
                     [
                         Constraint::Length(200),
+                        Constraint::Length(20),
                         Constraint::Length(40),
                         Constraint::Length(40),
                         Constraint::Length(40),
@@ -467,6 +748,116 @@ fn render_transaction_tab(frame: &mut Frame, app: &mut App, area: Rect) {
                 frame.render_widget(paragraph, area);
             }
         }
+        "ERC1155 Transfers" => {
+            if !app.transfers.erc1155_transfers.is_empty() {
+                let header = ["Hash", "Time", "From", "To", "TokenId", "Amount"]
+                    .into_iter()
+                    .map(Cell::from)
+                    .collect::<Row>()
+                    .style(header_style)
+                    .height(2);
+                let rows = app.transfers.erc1155_transfers.iter().map(|data| {
+                    let item = [
+                        truncate(&data.hash),
+                        format_timestamp(data.timestamp, app.show_local_time),
+                        address_label(app, &data.from),
+                        address_label(app, &data.to),
+                        truncate(&data.token_id),
+                        truncate(&data.amount),
+                    ];
+                    item.into_iter()
+                        .map(|content| Cell::from(Text::from(content)))
+                        .collect::<Row>()
+                        .style(Style::new().fg(Color::Yellow).bg(Color::DarkGray))
+                        .height(1)
+                });
+                let table = Table::new(
+                    rows,
+                    [
+                        Constraint::Length(200),
+                        Constraint::Length(20),
+                        Constraint::Length(40),
+                        Constraint::Length(40),
+                        Constraint::Length(40),
+                        Constraint::Length(40),
+                    ],
+                )
+                .header(header)
+                .block(
+                    Block::bordered()
+                        .border_style(Style::new().green())
+                        .padding(Padding::horizontal(2)),
+                )
+                .highlight_style(selected_style)
+                .highlight_spacing(HighlightSpacing::Always);
+                frame.render_stateful_widget(table, chunks[0], &mut app.table_states.erc1155_table);
+                render_scrollbar(frame, app, chunks[0]);
+                render_tansaction_details(frame, app, right_panel[0]);
+                render_erc1155_metrics(frame, app, bottom_right_panel[0]);
+                render_erc1155_chart(frame, app, bottom_right_panel[1]);
+            } else {
+                let text = Text::from("\n\n\nNo ERC1155 transfers found.");
+                let paragraph = Paragraph::new(text).alignment(Alignment::Center);
+                frame.render_widget(paragraph, area);
+            }
+        }
+        "Unknown Transfers" => {
+            if !app.transfers.unknown_transfers.is_empty() {
+                // Without a known layout the indexed topics are shown in order
+                let header = ["Hash", "Time", "Contract", "Topic 1", "Topic 2"]
+                    .into_iter()
+                    .map(Cell::from)
+                    .collect::<Row>()
+                    .style(header_style)
+                    .height(2);
+                let rows = app.transfers.unknown_transfers.iter().map(|data| {
+                    let addresses = data.indexed_addresses();
+                    let topic = |i: usize| {
+                        addresses
+                            .get(i)
+                            .map_or("-".to_string(), |address| address_label(app, address))
+                    };
+                    let item = [
+                        truncate(&data.hash),
+                        format_timestamp(data.timestamp, app.show_local_time),
+                        contract_label(app, &data.contract),
+                        topic(0),
+                        topic(1),
+                    ];
+                    item.into_iter()
+                        .map(|content| Cell::from(Text::from(content)))
+                        .collect::<Row>()
+                        .style(Style::new().fg(Color::Yellow).bg(Color::DarkGray))
+                        .height(1)
+                });
+                let table = Table::new(
+                    rows,
+                    [
+                        Constraint::Length(200),
+                        Constraint::Length(20),
+                        Constraint::Length(40),
+                        Constraint::Length(40),
+                        Constraint::Length(40),
+                    ],
+                )
+                .header(header)
+                .block(
+                    Block::bordered()
+                        .border_style(Style::new().green())
+                        .padding(Padding::horizontal(2)),
+                )
+                .highlight_style(selected_style)
+                .highlight_spacing(HighlightSpacing::Always);
+                frame.render_stateful_widget(table, chunks[0], &mut app.table_states.unknown_table);
+                render_scrollbar(frame, app, chunks[0]);
+                render_tansaction_details(frame, app, right_panel[0]);
+                render_unknown_data(frame, app, bottom_right_panel[0]);
+            } else {
+                let text = Text::from("\n\n\nNo unknown transfers found.");
+                let paragraph = Paragraph::new(text).alignment(Alignment::Center);
+                frame.render_widget(paragraph, area);
+            }
+        }
         _ => {}
     }
 }
@@ -515,56 +906,200 @@ fn render_scrollbar(frame: &mut Frame, app: &mut App, area: Rect) {
                 &mut app.scrollbar_states.erc721_scrollbar,
             );
         }
+        "ERC1155 Transfers" => {
+            frame.render_stateful_widget(
+                Scrollbar::default()
+                    .style(Style::new().green())
+                    .orientation(ScrollbarOrientation::VerticalRight)
+                    .begin_symbol(None)
+                    .end_symbol(None),
+                area.inner(Margin {
+                    vertical: 3,
+                    horizontal: 1,
+                }),
+                &mut app.scrollbar_states.erc1155_scrollbar,
+            );
+        }
+        "Unknown Transfers" => {
+            frame.render_stateful_widget(
+                Scrollbar::default()
+                    .style(Style::new().green())
+                    .orientation(ScrollbarOrientation::VerticalRight)
+                    .begin_symbol(None)
+                    .end_symbol(None),
+                area.inner(Margin {
+                    vertical: 3,
+                    horizontal: 1,
+                }),
+                &mut app.scrollbar_states.unknown_scrollbar,
+            );
+        }
         _ => {}
     }
 }
 
 fn render_tansaction_details(frame: &mut Frame, app: &mut App, area: Rect) {
-    let mut fields: Vec<(&str, &str)> = Vec::new();
+    let mut fields: Vec<(&str, String)> = Vec::new();
+    let time_zone = if app.show_local_time { "Local" } else { "UTC" };
 
     match app.transaction_tabs.index {
         0 => {
             if let Some(index) = app.table_states.regular_table.selected() {
                 let selected_transaction = &app.transfers.regular_transfers[index];
                 fields = vec![
-                    ("Hash:    ", selected_transaction.hash.as_str()),
-                    ("Block:   ", selected_transaction.block.as_str()),
-                    ("From:    ", selected_transaction.from.as_str()),
-                    ("To:      ", selected_transaction.to.as_str()),
-                    ("Value:    \u{27E0}", &selected_transaction.value[..5]),
+                    ("Hash:    ", selected_transaction.hash.clone()),
+                    ("Block:   ", selected_transaction.block.clone()),
+                    (
+                        "Time:    ",
+                        format!(
+                            "{} {}",
+                            format_timestamp(selected_transaction.timestamp, app.show_local_time),
+                            time_zone
+                        ),
+                    ),
+                    ("From:    ", with_label(app, &selected_transaction.from)),
+                    ("To:      ", with_label(app, &selected_transaction.to)),
+                    (
+                        "Value:   ",
+                        format!(
+                            "{} {}",
+                            &selected_transaction.value[..5],
+                            app.query.chain.native_symbol
+                        ),
+                    ),
                 ];
+                if selected_transaction.internal {
+                    fields.push(("Tag:     ", "Internal".to_string()));
+                }
             }
         }
         1 => {
             if let Some(index) = app.table_states.erc20_table.selected() {
                 let selected_transaction = &app.transfers.erc20_transfers[index];
                 fields = vec![
-                    ("Hash:    ", selected_transaction.hash.as_str()),
-                    ("Block:   ", selected_transaction.block.as_str()),
-                    ("Contract:", selected_transaction.contract.as_str()),
-                    ("From:    ", selected_transaction.from.as_str()),
-                    ("To:      ", selected_transaction.to.as_str()),
-                    ("Amount:  ", &selected_transaction.amount),
+                    ("Hash:    ", selected_transaction.hash.clone()),
+                    ("Block:   ", selected_transaction.block.clone()),
+                    (
+                        "Time:    ",
+                        format!(
+                            "{} {}",
+                            format_timestamp(selected_transaction.timestamp, app.show_local_time),
+                            time_zone
+                        ),
+                    ),
+                    ("Contract:", with_label(app, &selected_transaction.contract)),
+                    ("From:    ", with_label(app, &selected_transaction.from)),
+                    ("To:      ", with_label(app, &selected_transaction.to)),
+                    ("Amount:  ", app.erc20_amount(selected_transaction)),
                 ];
+                if let Some(token) = app.tokens.describe(&selected_transaction.contract) {
+                    fields.push(("Token:   ", token));
+                }
+                if selected_transaction.internal {
+                    fields.push(("Tag:     ", "Internal".to_string()));
+                }
             }
         }
         2 => {
-            if !app.transfers.erc721_transfers.is_empty() {
-                if let Some(index) = app.table_states.erc721_table.selected() {
-                    let selected_transaction = &app.transfers.erc721_transfers[index];
-                    fields = vec![
-                        ("Hash:    ", selected_transaction.hash.as_str()),
-                        ("Block:   ", selected_transaction.block.as_str()),
-                        ("Contract:", selected_transaction.contract.as_str()),
-                        ("From:    ", selected_transaction.from.as_str()),
-                        ("To:      ", selected_transaction.to.as_str()),
-                        ("TokenId: ", &selected_transaction.token_id),
-                    ];
+            let selected = app.table_states.erc721_table.selected();
+            if let Some(selected_transaction) =
+                selected.and_then(|index| app.transfers.erc721_transfers.get(index))
+            {
+                fields = vec![
+                    ("Hash:    ", selected_transaction.hash.clone()),
+                    ("Block:   ", selected_transaction.block.clone()),
+                    (
+                        "Time:    ",
+                        format!(
+                            "{} {}",
+                            format_timestamp(selected_transaction.timestamp, app.show_local_time),
+                            time_zone
+                        ),
+                    ),
+                    ("Contract:", with_label(app, &selected_transaction.contract)),
+                    ("From:    ", with_label(app, &selected_transaction.from)),
+                    ("To:      ", with_label(app, &selected_transaction.to)),
+                    ("TokenId: ", selected_transaction.token_id.clone()),
+                ];
+                if let Some(token) = app.tokens.describe(&selected_transaction.contract) {
+                    fields.push(("Token:   ", token));
+                }
+                if selected_transaction.internal {
+                    fields.push(("Tag:     ", "Internal".to_string()));
+                }
+            }
+        }
+        3 => {
+            let selected = app.table_states.erc1155_table.selected();
+            if let Some(selected_transaction) =
+                selected.and_then(|index| app.transfers.erc1155_transfers.get(index))
+            {
+                fields = vec![
+                    ("Hash:    ", selected_transaction.hash.clone()),
+                    ("Block:   ", selected_transaction.block.clone()),
+                    (
+                        "Time:    ",
+                        format!(
+                            "{} {}",
+                            format_timestamp(selected_transaction.timestamp, app.show_local_time),
+                            time_zone
+                        ),
+                    ),
+                    ("Contract:", with_label(app, &selected_transaction.contract)),
+                    ("Operator:", with_label(app, &selected_transaction.operator)),
+                    ("From:    ", with_label(app, &selected_transaction.from)),
+                    ("To:      ", with_label(app, &selected_transaction.to)),
+                    ("TokenId: ", selected_transaction.token_id.clone()),
+                    ("Amount:  ", selected_transaction.amount.clone()),
+                ];
+                if let Some(token) = app.tokens.describe(&selected_transaction.contract) {
+                    fields.push(("Token:   ", token));
+                }
+                if selected_transaction.internal {
+                    fields.push(("Tag:     ", "Internal".to_string()));
+                }
+            }
+        }
+        4 => {
+            let selected = app.table_states.unknown_table.selected();
+            if let Some(selected_transaction) =
+                selected.and_then(|index| app.transfers.unknown_transfers.get(index))
+            {
+                fields = vec![
+                    ("Hash:    ", selected_transaction.hash.clone()),
+                    ("Block:   ", selected_transaction.block.clone()),
+                    (
+                        "Time:    ",
+                        format!(
+                            "{} {}",
+                            format_timestamp(selected_transaction.timestamp, app.show_local_time),
+                            time_zone
+                        ),
+                    ),
+                    ("Contract:", with_label(app, &selected_transaction.contract)),
+                ];
+                let topics = ["Topic 1: ", "Topic 2: ", "Topic 3: "];
+                for (name, address) in topics
+                    .into_iter()
+                    .zip(selected_transaction.indexed_addresses())
+                {
+                    fields.push((name, with_label(app, &address)));
+                }
+                let data_len = selected_transaction.data.len().saturating_sub(2) / 2;
+                fields.push(("Data:    ", format!("{} bytes", data_len)));
+                if let Some(token) = app.tokens.describe(&selected_transaction.contract) {
+                    fields.push(("Token:   ", token));
                 }
             }
         }
         _ => {}
     }
+    let explorer_link = fields
+        .first()
+        .and_then(|(_, hash)| app.query.chain.explorer_link(hash));
+    if let Some(link) = explorer_link {
+        fields.push(("Explorer:", link));
+    }
     let rows = fields.iter().enumerate().map(|(_i, data)| {
         let item = [format!("{} {}", data.0, data.1)];
         item.into_iter()
@@ -594,9 +1129,35 @@ fn render_tansaction_details(frame: &mut Frame, app: &mut App, area: Rect) {
     frame.render_widget(table, area);
 }
 
-fn render_bar_chart(frame: &mut Frame, app: &mut App, area: Rect) {
-    let mut highest_values: Vec<f64> = Vec::new();
-    let mut high_values: Vec<f64> = Vec::new();
+// Raw data of the selected unknown transfer, split into 32 byte words
+fn render_unknown_data(frame: &mut Frame, app: &App, area: Rect) {
+    let data = app
+        .table_states
+        .unknown_table
+        .selected()
+        .and_then(|index| app.transfers.unknown_transfers.get(index))
+        .map(|transfer| transfer.data.trim_start_matches("0x"))
+        .unwrap_or_default();
+    let words: Vec<Line> = data
+        .as_bytes()
+        .chunks(64)
+        .map(|word| Line::from(String::from_utf8_lossy(word).to_string()))
+        .collect();
+    let paragraph = Paragraph::new(words)
+        .style(Style::new().fg(Color::Yellow))
+        .wrap(Wrap { trim: false })
+        .block(
+            Block::bordered()
+                .border_style(Style::new().green())
+                .padding(Padding::horizontal(2))
+                .title("Data"),
+        );
+    frame.render_widget(paragraph, area);
+}
+
+fn render_bar_chart(frame: &mut Frame, app: &mut App, area: Rect) {
+    let mut highest_values: Vec<f64> = Vec::new();
+    let mut high_values: Vec<f64> = Vec::new();
     let mut medium_values: Vec<f64> = Vec::new();
     let mut low_values: Vec<f64> = Vec::new();
     let mut lowest_values: Vec<f64> = Vec::new();
@@ -686,16 +1247,10 @@ fn render_erc20_chart(frame: &mut Frame, app: &App, area: Rect) {
     let bars: Vec<Bar> = sorted_contracts
         .iter()
         .map(|v| *v)
-        .enumerate()
-        .map(|(i, value)| {
+        .map(|value| {
             Bar::default()
                 .value(*value.1 as u64)
-                .label(Line::from(format!(
-                    "{}",
-                    match i {
-                        _ => truncate(value.0),
-                    }
-                )))
+                .label(Line::from(contract_label(app, value.0)))
                 .text_value(format!("{}", value.1.to_string()))
                 .style(Style::new().yellow())
                 .value_style(Style::new())
@@ -744,16 +1299,10 @@ fn render_erc721_chart(frame: &mut Frame, app: &App, area: Rect) {
     let bars: Vec<Bar> = sorted_contracts
         .iter()
         .map(|v| *v)
-        .enumerate()
-        .map(|(i, value)| {
+        .map(|value| {
             Bar::default()
                 .value(*value.1 as u64)
-                .label(Line::from(format!(
-                    "{}",
-                    match i {
-                        _ => truncate(value.0),
-                    }
-                )))
+                .label(Line::from(contract_label(app, value.0)))
                 .text_value(format!("{}", value.1.to_string()))
                 .style(Style::new().yellow())
                 .value_style(Style::new())
@@ -777,6 +1326,50 @@ fn render_erc721_chart(frame: &mut Frame, app: &App, area: Rect) {
     frame.render_widget(bar_chart, area)
 }
 
+fn render_erc1155_chart(frame: &mut Frame, app: &App, area: Rect) {
+    let mut interactions_per_contract: Vec<(&String, usize)> = Vec::new();
+
+    for transfer in &app.transfers.erc1155_transfers {
+        match interactions_per_contract
+            .iter_mut()
+            .find(|(contract, _)| *contract == &transfer.contract)
+        {
+            Some((_, count)) => *count += 1,
+            None => interactions_per_contract.push((&transfer.contract, 1)),
+        }
+    }
+
+    interactions_per_contract.sort_by_key(|&(_, count)| Reverse(count));
+
+    let bars: Vec<Bar> = interactions_per_contract
+        .iter()
+        .map(|(contract, count)| {
+            Bar::default()
+                .value(*count as u64)
+                .label(Line::from(contract_label(app, contract)))
+                .text_value(count.to_string())
+                .style(Style::new().yellow())
+                .value_style(Style::new())
+        })
+        .collect();
+    let title = Line::from("Most Interactions").centered();
+
+    let bar_chart = BarChart::default()
+        .data(BarGroup::default().bars(&bars))
+        .direction(Direction::Horizontal)
+        .bar_width(1)
+        .bar_gap(0)
+        .block(
+            Block::new()
+                .title(title)
+                .borders(Borders::ALL)
+                .style(Style::new().green())
+                .padding(Padding::uniform(1)),
+        );
+
+    frame.render_widget(bar_chart, area)
+}
+
 fn render_regular_metrics(frame: &mut Frame, app: &mut App, area: Rect) {
     let style = Style::new().yellow();
     let mut total_sent: f64 = 0.0;
@@ -785,24 +1378,44 @@ fn render_regular_metrics(frame: &mut Frame, app: &mut App, area: Rect) {
     let mut total_received: f64 = 0.0;
     let mut highest_sent: f64 = 0.0;
     let mut highest_received: f64 = 0.0;
+    let mut num_internal: usize = 0;
     let mut unique_to: Vec<&String> = Vec::new();
     let mut unique_from: Vec<&String> = Vec::new();
+    let mut counterparties: Vec<(&String, usize)> = Vec::new();
+    let addresses = app.query.address_list();
 
     for transfer in &app.transfers.regular_transfers {
         let value = transfer.value.parse::<f64>().unwrap();
 
-        if transfer.from.to_lowercase() == app.query.address.to_lowercase() {
+        let counterparty = if transfer.internal {
+            num_internal += 1;
+            None
+        } else if addresses.contains(&transfer.from.to_lowercase()) {
             num_sent += 1;
             total_sent += value;
             if value > highest_sent {
                 highest_sent = value;
             }
-        } else if transfer.to.to_lowercase() == app.query.address.to_lowercase() {
+            Some(&transfer.to)
+        } else if addresses.contains(&transfer.to.to_lowercase()) {
             num_received += 1;
             total_received += value;
             if value > highest_received {
                 highest_received = value
             }
+            Some(&transfer.from)
+        } else {
+            None
+        };
+
+        if let Some(counterparty) = counterparty {
+            match counterparties
+                .iter_mut()
+                .find(|(address, _)| *address == counterparty)
+            {
+                Some((_, count)) => *count += 1,
+                None => counterparties.push((counterparty, 1)),
+            }
         }
 
         if !unique_to.contains(&&transfer.to) {
@@ -813,6 +1426,11 @@ fn render_regular_metrics(frame: &mut Frame, app: &mut App, area: Rect) {
         }
     }
 
+    let top_counterparty = match counterparties.iter().max_by_key(|(_, count)| *count) {
+        Some((address, count)) => format!("{} ({})", address_label(app, address), count),
+        None => "-".to_string(),
+    };
+
     let avg_sent = total_sent / num_sent.to_string().parse::<f64>().unwrap();
     let avg_received = total_received / num_received.to_string().parse::<f64>().unwrap();
 
@@ -832,6 +1450,10 @@ fn render_regular_metrics(frame: &mut Frame, app: &mut App, area: Rect) {
             format!("Incoming:           {}", num_received),
             style,
         ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Internal:           {}", num_internal),
+            style,
+        ))),
         ListItem::new(Line::from(Span::styled(
             format!("Total Sent:         {:.4}", total_sent),
             style,
@@ -864,6 +1486,10 @@ fn render_regular_metrics(frame: &mut Frame, app: &mut App, area: Rect) {
             format!("Unique Recipients:  {}", unique_from.len()),
             style,
         ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Top Counterparty:   {}", top_counterparty),
+            style,
+        ))),
     ];
 
     let list = List::new(list_items).block(
@@ -883,7 +1509,9 @@ fn render_erc20_metrics(frame: &mut Frame, app: &App, area: Rect) {
     let mut interactions_per_contract: Vec<usize> = Vec::new();
     let mut num_from: usize = 0;
     let mut num_to: usize = 0;
+    let mut num_internal: usize = 0;
     let mut most_interacted: Option<(&String, usize)> = None;
+    let addresses = app.query.address_list();
 
     for transfer in &app.transfers.erc20_transfers {
         let contract = &transfer.contract;
@@ -895,11 +1523,16 @@ fn render_erc20_metrics(frame: &mut Frame, app: &App, area: Rect) {
             interactions_per_contract.push(1);
         }
 
-        if transfer.from.to_lowercase() == app.query.address.to_lowercase() {
+        if transfer.internal {
+            num_internal += 1;
+            continue;
+        }
+
+        if addresses.contains(&transfer.from.to_lowercase()) {
             num_from += 1;
         }
 
-        if transfer.to.to_lowercase() == app.query.address.to_lowercase() {
+        if addresses.contains(&transfer.to.to_lowercase()) {
             num_to += 1;
         }
     }
@@ -916,6 +1549,32 @@ fn render_erc20_metrics(frame: &mut Frame, app: &App, area: Rect) {
     let avg_interactions_per_contract =
         app.transfers.erc20_transfers.len() / unique_contracts.len();
 
+    // Amounts of different tokens don't add up, so volume is only shown for the top token
+    let (top_token, top_token_volume) = match most_interacted {
+        Some((contract, count)) => match app.tokens.get(contract) {
+            Some(token) => {
+                let volume: f64 = app
+                    .transfers
+                    .erc20_transfers
+                    .iter()
+                    .filter(|transfer| &transfer.contract == contract)
+                    .filter_map(|transfer| {
+                        token.scale_amount(&transfer.amount)?.parse::<f64>().ok()
+                    })
+                    .sum();
+                (
+                    format!("{} ({})", contract_label(app, contract), count),
+                    format!("{:.4} {}", volume, token.symbol),
+                )
+            }
+            None => (
+                format!("{} ({})", contract_label(app, contract), count),
+                "-".to_string(),
+            ),
+        },
+        None => ("-".to_string(), "-".to_string()),
+    };
+
     let style = Style::new().yellow();
     let list_items = [
         ListItem::new(Line::from(Span::styled(
@@ -933,6 +1592,10 @@ fn render_erc20_metrics(frame: &mut Frame, app: &App, area: Rect) {
             format!("Incoming:                      {}", num_to),
             style,
         ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Internal:                      {}", num_internal),
+            style,
+        ))),
         ListItem::new(Line::from(Span::styled(
             format!("Unique Contracts:              {}", unique_contracts.len()),
             style,
@@ -944,6 +1607,21 @@ fn render_erc20_metrics(frame: &mut Frame, app: &App, area: Rect) {
             ),
             style,
         ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Top Token:                     {}", top_token),
+            style,
+        ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Top Token Volume:              {}", top_token_volume),
+            style,
+        ))),
+        ListItem::new(Line::from(Span::styled(
+            format!(
+                "Unknown Transfers:             {}",
+                app.transfers.unknown_transfers.len()
+            ),
+            style,
+        ))),
     ];
 
     let list = List::new(list_items).block(
@@ -963,7 +1641,9 @@ fn render_erc721_metrics(frame: &mut Frame, app: &App, area: Rect) {
     let mut num_from: usize = 0;
     let mut num_to: usize = 0;
     let mut num_minted: usize = 0;
+    let mut num_internal: usize = 0;
     let mut most_interacted: Option<(&String, usize)> = None;
+    let addresses = app.query.address_list();
 
     for transfer in &app.transfers.erc721_transfers {
         let contract = &transfer.contract;
@@ -975,13 +1655,18 @@ fn render_erc721_metrics(frame: &mut Frame, app: &App, area: Rect) {
             interactions_per_contract.push(1);
         }
 
+        if transfer.internal {
+            num_internal += 1;
+            continue;
+        }
+
         if transfer.from == "0x0000000000000000000000000000000000000000" {
             num_minted += 1
-        } else if transfer.to.to_lowercase() == app.query.address.to_lowercase() {
+        } else if addresses.contains(&transfer.to.to_lowercase()) {
             num_to += 1;
         }
 
-        if transfer.from.to_lowercase() == app.query.address.to_lowercase() {
+        if addresses.contains(&transfer.from.to_lowercase()) {
             num_from += 1;
         }
     }
@@ -1015,6 +1700,103 @@ fn render_erc721_metrics(frame: &mut Frame, app: &App, area: Rect) {
             format!("Incoming:                      {}", num_to),
             style,
         ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Internal:                      {}", num_internal),
+            style,
+        ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Minted:                        {}", num_minted),
+            style,
+        ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Unique Contracts:              {}", unique_contracts.len()),
+            style,
+        ))),
+        ListItem::new(Line::from(Span::styled(
+            format!(
+                "Avg Transfers Per Contract:    {}",
+                avg_interactions_per_contract
+            ),
+            style,
+        ))),
+        ListItem::new(Line::from(Span::styled(
+            format!(
+                "Unknown Transfers:             {}",
+                app.transfers.unknown_transfers.len()
+            ),
+            style,
+        ))),
+    ];
+
+    let list = List::new(list_items).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .green()
+            .title("Metrics")
+            .title_alignment(Alignment::Center)
+            .padding(Padding::symmetric(2, 1)),
+    );
+
+    frame.render_widget(list, area);
+}
+
+fn render_erc1155_metrics(frame: &mut Frame, app: &App, area: Rect) {
+    let mut unique_contracts: Vec<&String> = Vec::new();
+    let mut unique_tokens: Vec<(&String, &String)> = Vec::new();
+    let mut num_from: usize = 0;
+    let mut num_to: usize = 0;
+    let mut num_minted: usize = 0;
+    let mut num_internal: usize = 0;
+    let addresses = app.query.address_list();
+
+    for transfer in &app.transfers.erc1155_transfers {
+        if !unique_contracts.contains(&&transfer.contract) {
+            unique_contracts.push(&transfer.contract);
+        }
+        if !unique_tokens.contains(&(&transfer.contract, &transfer.token_id)) {
+            unique_tokens.push((&transfer.contract, &transfer.token_id));
+        }
+
+        if transfer.internal {
+            num_internal += 1;
+            continue;
+        }
+
+        if transfer.from == "0x0000000000000000000000000000000000000000" {
+            num_minted += 1
+        } else if addresses.contains(&transfer.to.to_lowercase()) {
+            num_to += 1;
+        }
+
+        if addresses.contains(&transfer.from.to_lowercase()) {
+            num_from += 1;
+        }
+    }
+
+    let avg_interactions_per_contract =
+        app.transfers.erc1155_transfers.len() / unique_contracts.len();
+
+    let style = Style::new().yellow();
+    let list_items = [
+        ListItem::new(Line::from(Span::styled(
+            format!(
+                "Total Transfers:               {}",
+                app.transfers.erc1155_transfers.len()
+            ),
+            style,
+        ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Outgoing:                      {}", num_from),
+            style,
+        ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Incoming:                      {}", num_to),
+            style,
+        ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Internal:                      {}", num_internal),
+            style,
+        ))),
         ListItem::new(Line::from(Span::styled(
             format!("Minted:                        {}", num_minted),
             style,
@@ -1023,6 +1805,10 @@ fn render_erc721_metrics(frame: &mut Frame, app: &App, area: Rect) {
             format!("Unique Contracts:              {}", unique_contracts.len()),
             style,
         ))),
+        ListItem::new(Line::from(Span::styled(
+            format!("Unique Tokens:                 {}", unique_tokens.len()),
+            style,
+        ))),
         ListItem::new(Line::from(Span::styled(
             format!(
                 "Avg Transfers Per Contract:    {}",
@@ -1067,16 +1853,190 @@ fn render_exit_popup(frame: &mut Frame, area: Rect) {
     frame.render_widget(exit_paragraph, inner_rect);
 }
 
+fn render_cancel_popup(frame: &mut Frame, app: &App, area: Rect) {
+    let outer_rect = centered_rect(42, 32, area);
+    let inner_rect = centered_rect(40, 30, area);
+    frame.render_widget(Clear, outer_rect);
+
+    let popup_block = Block::default()
+        .green()
+        .borders(Borders::ALL)
+        .padding(Padding::uniform(1))
+        .style(Style::default().bg(Color::DarkGray));
+
+    let text = Text::styled(
+        format!(
+            "\n\nQuery cancelled after scanning up to block {}. \n\nKeep the {} transfers decoded so far? \n\n\n\n Keep: 'y' | Discard: 'n'",
+            app.query_progress.next_block.saturating_sub(1),
+            app.num_transfers()
+        ),
+        Style::new().green(),
+    );
+
+    let cancel_paragraph = Paragraph::new(text)
+        .block(popup_block)
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(cancel_paragraph, inner_rect);
+}
+
+fn render_label_popup(frame: &mut Frame, app: &App, area: Rect) {
+    let outer_rect = centered_rect(42, 32, area);
+    let inner_rect = centered_rect(40, 30, area);
+    frame.render_widget(Clear, outer_rect);
+
+    let popup_block = Block::default()
+        .green()
+        .borders(Borders::ALL)
+        .title(" Address Book ")
+        .title_alignment(Alignment::Center)
+        .padding(Padding::uniform(1))
+        .style(Style::default().bg(Color::DarkGray));
+
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(format!("Label for {}", app.label_address)),
+        Line::from(""),
+        Line::from(format!("> {}_", app.label_input)).yellow(),
+        Line::from(""),
+    ];
+    if let Some(err) = &app.label_error {
+        lines.push(Line::from(err.as_str()).red());
+        lines.push(Line::from(""));
+    }
+    lines.push(Line::from(
+        " Save: ENTER | Cancel: ESC | Empty label removes it",
+    ));
+
+    let paragraph = Paragraph::new(Text::from(lines))
+        .block(popup_block)
+        .style(Style::new().green())
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(paragraph, inner_rect);
+}
+
+fn render_chain_popup(frame: &mut Frame, app: &mut App, area: Rect) {
+    let outer_rect = centered_rect(52, 52, area);
+    let inner_rect = centered_rect(50, 50, area);
+    frame.render_widget(Clear, outer_rect);
+
+    let list_items: Vec<ListItem> = app
+        .config
+        .chains()
+        .iter()
+        .map(|chain| {
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{:<16}", chain.name), Style::new().yellow()),
+                Span::styled(format!("{:<10}", chain.id), Style::new().green()),
+                Span::styled(chain.url.clone(), Style::new().green()),
+            ]))
+        })
+        .collect();
+
+    let list = List::new(list_items)
+        .block(
+            Block::default()
+                .green()
+                .borders(Borders::ALL)
+                .title(" Select Chain ")
+                .title_bottom(Line::from(" Select: ENTER | Cancel: ESC ").centered())
+                .title_alignment(Alignment::Center)
+                .padding(Padding::uniform(1))
+                .style(Style::default().bg(Color::DarkGray)),
+        )
+        .highlight_symbol("> ")
+        .highlight_spacing(HighlightSpacing::Always);
+    frame.render_stateful_widget(list, inner_rect, &mut app.chain_state);
+}
+
+fn render_save_query_popup(frame: &mut Frame, app: &App, area: Rect) {
+    let outer_rect = centered_rect(42, 32, area);
+    let inner_rect = centered_rect(40, 30, area);
+    frame.render_widget(Clear, outer_rect);
+
+    let popup_block = Block::default()
+        .green()
+        .borders(Borders::ALL)
+        .title(" Save Query ")
+        .title_alignment(Alignment::Center)
+        .padding(Padding::uniform(1))
+        .style(Style::default().bg(Color::DarkGray));
+
+    let mut lines = vec![
+        Line::from(""),
+        Line::from("Name for the current query"),
+        Line::from(""),
+        Line::from(format!("> {}_", app.query_name_input)).yellow(),
+        Line::from(""),
+    ];
+    if let Some(err) = &app.query_name_error {
+        lines.push(Line::from(err.as_str()).red());
+        lines.push(Line::from(""));
+    }
+    lines.push(Line::from(
+        " Save: ENTER | Cancel: ESC | An existing name is replaced",
+    ));
+
+    let paragraph = Paragraph::new(Text::from(lines))
+        .block(popup_block)
+        .style(Style::new().green())
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(paragraph, inner_rect);
+}
+
+fn render_search_popup(frame: &mut Frame, app: &App, area: Rect) {
+    let outer_rect = centered_rect(42, 22, area);
+    let inner_rect = centered_rect(40, 20, area);
+    frame.render_widget(Clear, outer_rect);
+
+    let popup_block = Block::default()
+        .green()
+        .borders(Borders::ALL)
+        .title(" Search Address or Label ")
+        .title_alignment(Alignment::Center)
+        .padding(Padding::uniform(1))
+        .style(Style::default().bg(Color::DarkGray));
+
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(format!("/ {}_", app.search_input)).yellow(),
+        Line::from(""),
+    ];
+    if let Some(err) = &app.search_error {
+        lines.push(Line::from(err.as_str()).red());
+        lines.push(Line::from(""));
+    }
+    lines.push(Line::from(" Find: ENTER | Cancel: ESC"));
+
+    let paragraph = Paragraph::new(Text::from(lines))
+        .block(popup_block)
+        .style(Style::new().green())
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: false });
+    frame.render_widget(paragraph, inner_rect);
+}
+
 fn render_footer(frame: &mut Frame, app: &mut App, area: Rect) {
     let instructions_block = Block::default().padding(Padding::vertical(1));
     let mut content = "";
 
     match app.current_screen {
         CurrentScreen::Main => {
-            content = "\nUp: \u{21D1} | Down: \u{21D3} | Next Tab: TAB | Output JSON: 'j' | Quit: 'q'"
+            content = "\nUp: \u{21D1} | Down: \u{21D3} | Next Tab: TAB | UTC/Local Time: 't' | Label: 'l' | Search: '/' | Next Match: 'n' | Refresh: 'r' | History: 'h' | Export: 'j' | Quit: 'q'"
+        }
+        CurrentScreen::Loading => {
+            content = "\nUp: \u{21D1} | Down: \u{21D3} | Next Tab: TAB | UTC/Local Time: 't' | Cancel Query: 'q' / 'ESC'"
+        }
+        CurrentScreen::Error => {
+            content = "\nBack To Query Builder: ENTER / 'c' | History: 'h' | Quit: 'q'"
         }
         CurrentScreen::QueryBuilder => {
-            content = "\nToggle Edit Mode: 'e' / 'ESC' | Up: \u{21D1} | Down: \u{21D3} | Toggle Field: ENTER | Start Query: 'y' | Quit: 'q'"
+            content = "\nToggle Edit Mode: 'e' / 'ESC' | Up: \u{21D1} | Down: \u{21D3} | Toggle Field: ENTER | Start Query: 'y' | Save Query: 's' | History: 'h' | Quit: 'q'"
+        }
+        CurrentScreen::History => {
+            content = "\nUp: \u{21D1} | Down: \u{21D3} | Re-run: ENTER | Edit: 'e' | Open Cached Results: 'o' | Back: 'ESC' | Quit: 'q'"
         }
         _ => {}
     }
@@ -1088,7 +2048,7 @@ fn render_footer(frame: &mut Frame, app: &mut App, area: Rect) {
     frame.render_widget(instructions, area);
 }
 
-fn render_json_popup(frame: &mut Frame, area: Rect) {
+fn render_export_popup(frame: &mut Frame, app: &App, area: Rect) {
     let outer_rect = centered_rect(42, 32, area);
     let inner_rect = centered_rect(40, 30, area);
     frame.render_widget(Clear, outer_rect);
@@ -1096,19 +2056,78 @@ fn render_json_popup(frame: &mut Frame, area: Rect) {
     let popup_block = Block::default()
         .green()
         .borders(Borders::ALL)
+        .title(" Export ")
+        .title_alignment(Alignment::Center)
         .padding(Padding::uniform(1))
         .style(Style::default().bg(Color::DarkGray));
 
-    let text = Text::styled(
-        "\n\nThe json file will be saved to the outputs folder. \n\n\n\n\n Confirm: 'y' | Cancel: 'n'",
-        Style::new().green(),
-    );
+    let formats: Vec<Span> = ExportFormat::ALL
+        .iter()
+        .map(|format| {
+            let name = format!(" {} ", format.name());
+            if *format == app.export_format {
+                Span::styled(name, Style::new().black().on_green())
+            } else {
+                Span::styled(name, Style::new().green())
+            }
+        })
+        .collect();
+    let mut lines = vec![
+        Line::from(formats),
+        Line::from("Format: \u{2190}/\u{2192}"),
+        Line::from(""),
+        Line::from(match app.export_format {
+            ExportFormat::Parquet => "Directory to write the files to",
+            _ => "File to write to",
+        }),
+        Line::from(""),
+        Line::from(format!("> {}_", app.export_path)).yellow(),
+        Line::from(""),
+    ];
+    if app.is_confirming_overwrite {
+        lines.push(Line::from(format!("{} already exists", app.export_path.trim())).red());
+        lines.push(Line::from(" Overwrite: 'y' | Edit Path: 'n'"));
+    } else {
+        lines.push(Line::from(" Export: ENTER | Cancel: ESC"));
+    }
 
-    let exit_paragraph = Paragraph::new(text)
+    let paragraph = Paragraph::new(Text::from(lines))
         .block(popup_block)
+        .style(Style::new().green())
         .alignment(Alignment::Center)
         .wrap(Wrap { trim: false });
-    frame.render_widget(exit_paragraph, inner_rect);
+    frame.render_widget(paragraph, inner_rect);
+}
+
+fn render_toast(frame: &mut Frame, app: &App, area: Rect) {
+    let toast = match app.toast() {
+        Some(toast) => toast,
+        None => return,
+    };
+    let width = (toast.message.chars().count() as u16 + 4).min(area.width);
+    let toast_rect = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: (area.y + area.height).saturating_sub(4),
+        width,
+        height: 3,
+    }
+    .intersection(area);
+    frame.render_widget(Clear, toast_rect);
+
+    let style = if toast.is_error {
+        Style::new().red()
+    } else {
+        Style::new().green()
+    };
+    let paragraph = Paragraph::new(toast.message.as_str())
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().bg(Color::DarkGray)),
+        )
+        .style(style)
+        .alignment(Alignment::Center);
+    frame.render_widget(paragraph, toast_rect);
 }
 
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -1131,6 +2150,57 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         .split(popup_layout[1])[1]
 }
 
+fn wallets_label(addresses: &[String]) -> String {
+    match addresses {
+        [] => String::new(),
+        [address] => truncate(address),
+        [address, rest @ ..] => format!("{} +{}", truncate(address), rest.len()),
+    }
+}
+
+fn format_timestamp(timestamp: Option<u64>, local: bool) -> String {
+    match timestamp.and_then(|timestamp| DateTime::from_timestamp(timestamp as i64, 0)) {
+        Some(datetime) if local => datetime
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string(),
+        Some(datetime) => datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => "-".to_string(),
+    }
+}
+
+// Label, token symbol or collection name of a contract, else its truncated address
+fn contract_label(app: &App, contract: &str) -> String {
+    match app.address_book.label(contract) {
+        Some(label) => label.to_string(),
+        None => app
+            .tokens
+            .contract_name(contract)
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| truncate(contract)),
+    }
+}
+
+// Label or primary ENS name of an address, else its truncated address
+fn address_label(app: &App, address: &str) -> String {
+    match app.address_book.label(address).or(app.ens.name(address)) {
+        Some(label) => label.to_string(),
+        None => truncate(address),
+    }
+}
+
+// Full address followed by its label and ENS name, for the details panel
+fn with_label(app: &App, address: &str) -> String {
+    let names: Vec<&str> = [app.address_book.label(address), app.ens.name(address)]
+        .into_iter()
+        .flatten()
+        .collect();
+    match names.is_empty() {
+        true => address.to_string(),
+        false => format!("{} ({})", address, names.join(", ")),
+    }
+}
+
 fn truncate(content: &str) -> String {
     if content.len() >= 2 && content[..2] == *"0x" {
         format!(